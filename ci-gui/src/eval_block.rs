@@ -1,10 +1,22 @@
-use ci_lisp::{ast::{Function, Token, Value}, parser_types::Parser, parsers::CIStreamingLexer};
+use ci_lisp::{ast::{AstNode, Token}, env::Environment, palette::token_rgb, parser_types::{HasEnv, Parser, SeqParsers}, parsers::{CallSite, CIIntermediateTokenizer, CINewReplParser, CIStreamingLexer}, reduce::{reduce_steps, Order, DEFAULT_STEP_LIMIT}};
 use egui::Event;
 
 pub enum OutputType {
     Raw(Box<dyn std::fmt::Display>),
-    Error(String),
-    Graph(Function)
+    // the rendered error, and the calls that led to it, innermost first
+    Error(String, Vec<String>),
+}
+
+// The input reducing one beta step at a time. Working the steps out can take a while,
+// so it waits until the reduction is first opened
+#[derive(Default)]
+enum Reduction {
+    #[default]
+    Nothing,
+    // the input, and the names that were defined before it was evaluated
+    Pending(AstNode, Environment),
+    // one line per step, starting with the input
+    Steps(Vec<String>),
 }
 
 #[derive(Default)]
@@ -14,7 +26,8 @@ pub struct LispEvalBlock {
     input_text: String,
     cursor_pos: usize,
 
-    output_text: Option<OutputType>
+    output_text: Option<OutputType>,
+    reduction: Reduction
 }
 
 impl LispEvalBlock {
@@ -34,13 +47,8 @@ impl LispEvalBlock {
             '[' => { self.input_text.insert(self.cursor_pos, '['); self.input_text.insert(self.cursor_pos + 1, ']'); self.cursor_pos += 1; }
             '{' => { self.input_text.insert(self.cursor_pos, '{'); self.input_text.insert(self.cursor_pos + 1, '}'); self.cursor_pos += 1; }
             '"' => { self.input_text.insert(self.cursor_pos, '"'); self.input_text.insert(self.cursor_pos + 1, '"'); self.cursor_pos += 1; }
-            ')' | ']' | '}' => {
-                if self.cursor_pos < self.input_text.len() && self.input_text.chars().nth(self.cursor_pos) == Some(ch) {
-                    self.cursor_pos += 1;
-                } else {
-                    self.input_text.insert(self.cursor_pos, ch);
-                    self.cursor_pos += 1;
-                }
+            ')' | ']' | '}' if self.cursor_pos < self.input_text.len() && self.input_text.chars().nth(self.cursor_pos) == Some(ch) => {
+                self.cursor_pos += 1;
             }
             _ => {
                 self.input_text.insert(self.cursor_pos, ch);
@@ -84,8 +92,8 @@ impl LispEvalBlock {
     pub fn handle_input(&mut self, event: Event) {
         match event {
             egui::Event::Key {key: egui::Key::Backspace, pressed: true, ..} => self.handle_backspace(),
-            egui::Event::Key {key: egui::Key::ArrowLeft, pressed: true, ..} => if self.cursor_pos > 0 {self.cursor_pos -= 1},
-            egui::Event::Key {key: egui::Key::ArrowRight, pressed: true, ..} => if self.cursor_pos < self.input_text.len() { self.cursor_pos += 1},
+            egui::Event::Key {key: egui::Key::ArrowLeft, pressed: true, ..} if self.cursor_pos > 0 => self.cursor_pos -= 1,
+            egui::Event::Key {key: egui::Key::ArrowRight, pressed: true, ..} if self.cursor_pos < self.input_text.len() => self.cursor_pos += 1,
 
             egui::Event::Key {key: egui::Key::L, pressed: true, modifiers, ..} if modifiers.ctrl => {
                 self.input_text.clear();
@@ -102,18 +110,12 @@ impl LispEvalBlock {
     }
 
     fn token_color(token: &Token) -> egui::Color32 {
-        match &token {
-            Token::Value(Value::Int(_)) => egui::Color32::from_rgb(200, 150, 255),   // purple
-            Token::Value(Value::String(_)) => egui::Color32::from_rgb(255, 200, 100),// orange
-            Token::Value(Value::Symbol(_)) => egui::Color32::from_rgb(100, 200, 255),// blue
-            Token::Value(Value::Ident(_)) => egui::Color32::from_rgb(150, 255, 150), // green
-            Token::Value(Value::True) | Token::Value(Value::Nil) => egui::Color32::LIGHT_BLUE,
-            _ => egui::Color32::WHITE,
-        }
+        let (r, g, b) = token_rgb(token);
+        egui::Color32::from_rgb(r, g, b)
     }
 
     // yeah, this logic is painful
-    pub fn token_str(i: usize, tokens: &Vec<Token>) -> String {
+    pub fn token_str(i: usize, tokens: &[Token]) -> String {
         let opener = |t: &Token| matches!(t, Token::LParen | Token::LCurly | Token::LBracket);
         let closer = |t: &Token| matches!(t, Token::RParen | Token::RCurly | Token::RBracket);
     
@@ -164,21 +166,24 @@ impl LispEvalBlock {
         let mut pos_x = ui.cursor().min.x + 4.0;
         let start_y = ui.cursor().min.y + 4.0;
 
-        // Tokenize
-        let tokens = CIStreamingLexer::default()
-            .parse(self.input_text.clone())
-            .unwrap();
+        // Tokenize, showing the text uncoloured when it doesn't lex, eg. with an invalid escape
+        let segments: Vec<(String, egui::Color32)> = match CIStreamingLexer::default().parse(self.input_text.clone()) {
+            Ok(tokens) => {
+                let tokens: Vec<Token> = tokens.into_iter().map(|t| t.node).collect();
+                tokens.iter()
+                    .enumerate()
+                    .map(|(i, token)| (Self::token_str(i, &tokens), Self::token_color(token)))
+                    .collect()
+            }
+            Err(_) => vec![(self.input_text.clone(), egui::Color32::WHITE)],
+        };
 
 
         let mut caret_x = pos_x;
         let mut caret_set = false;
         let mut char_index = 0;
 
-        for (i, token) in tokens.iter().enumerate() {
-            let token_str = Self::token_str(i, &tokens);
-
-            let color = Self::token_color(&token);
-
+        for (token_str, color) in segments {
             let galley = ui.painter().layout_no_wrap(token_str.clone(), font_id.clone(), color);
             ui.painter().galley(egui::pos2(pos_x, start_y), galley.clone(), color);
 
@@ -222,33 +227,107 @@ impl LispEvalBlock {
                     ui.painter().galley(output_pos, output_galley.clone(), output_color);
                     ui.add_space(font_id.size + output_galley.size().y + 28.0);
                 },
-                OutputType::Error(e) => {
+                OutputType::Error(e, backtrace) => {
                     let output_color = egui::Color32::from_rgb(255, 80, 80);
 
                     let output_galley = ui.painter().layout_no_wrap(e.to_string(), output_font, output_color);
                     let output_pos = egui::pos2(ui.cursor().min.x, ui.cursor().min.y + font_id.size + 4.0);
                     ui.painter().galley(output_pos, output_galley.clone(), output_color);
                     ui.add_space(font_id.size + output_galley.size().y + 28.0);
+
+                    if !backtrace.is_empty() {
+                        egui::CollapsingHeader::new("backtrace").show(ui, |ui| {
+                            for call in backtrace {
+                                ui.label(egui::RichText::new(call).monospace().color(output_color));
+                            }
+                        });
+                    }
                 },
-                OutputType::Graph(_) => todo!(),
+            }
+
+            let title = match &self.reduction {
+                Reduction::Nothing => None,
+                Reduction::Pending(_, _) => Some("reduction".to_string()),
+                Reduction::Steps(lines) => Some(format!("reduction ({} steps)", lines.len().saturating_sub(1))),
+            };
+            if let Some(title) = title {
+                egui::CollapsingHeader::new(title).id_salt("reduction").show(ui, |ui| {
+                    if let Reduction::Pending(term, env) = &self.reduction {
+                        self.reduction = Reduction::Steps(Self::reduction_lines(term, env));
+                    }
+                    let Reduction::Steps(lines) = &self.reduction else {
+                        return;
+                    };
+
+                    if lines.is_empty() {
+                        ui.label(egui::RichText::new("nothing to reduce").monospace().color(egui::Color32::from_gray(180)));
+                    }
+                    for line in lines {
+                        ui.label(egui::RichText::new(line).monospace().color(egui::Color32::from_gray(180)));
+                    }
+                });
             }
         } else {
             ui.add_space(font_id.size + 24.0);
         }
     }
 
+    // one line per call, with runs of the same call counted instead of repeated
+    fn backtrace_lines(trace: &[CallSite]) -> Vec<String> {
+        let mut lines: Vec<(String, usize)> = Vec::new();
+        for call in trace.iter().map(|call| call.to_string()) {
+            match lines.last_mut() {
+                Some((line, count)) if *line == call => *count += 1,
+                _ => lines.push((call, 1)),
+            }
+        }
+
+        lines.into_iter()
+            .map(|(line, count)| match count {
+                1 => line,
+                n => format!("{line} ({n} times)"),
+            })
+            .collect()
+    }
+
+    // the input, left to reduce once the reduction is opened, with the names defined before it's evaluated
+    fn reduction(&self, env: Environment) -> Reduction {
+        let parser = SeqParsers::new(
+            SeqParsers::new(CIStreamingLexer::default(), CIIntermediateTokenizer::default()),
+            CINewReplParser::default()
+        );
+        match parser.parse(self.input_text.clone()) {
+            Ok(term) => Reduction::Pending(term, env),
+            Err(_) => Reduction::Nothing,
+        }
+    }
+
+    // the steps `term` takes to reduce
+    fn reduction_lines(term: &AstNode, env: &Environment) -> Vec<String> {
+        let steps = reduce_steps(term, env, Order::Normal, DEFAULT_STEP_LIMIT);
+        if steps.is_empty() {
+            return Vec::new();
+        }
+
+        std::iter::once(format!("   {term}"))
+            .chain(steps.iter().map(|step| step.to_string()))
+            .collect()
+    }
+
     pub fn eval_block<P, T>(&mut self, evaluator: &mut P)
     where
         T: std::fmt::Display + 'static + Clone,
-        P: Parser<Input = String, Output = T>,
+        P: Parser<Input = String, Output = T> + HasEnv,
     {
+        self.reduction = self.reduction(evaluator.env());
+
         let res = evaluator.parse(self.input_text.clone());
         self.output_text = match res {
             Ok(s) => {
                 Some(OutputType::Raw(Box::new(s.clone())))
             }
             Err(e) => {
-                Some(OutputType::Error(format!("{}", e)))
+                Some(OutputType::Error(e.render_error(), Self::backtrace_lines(e.backtrace())))
             }
         };
     }
@@ -82,8 +82,9 @@ impl LispEditor {
             }
         }
 
-        for i in self.block.iter_mut() {
-            i.show(ui)
+        // each block gets its own id, so their backtraces open and close separately
+        for (index, i) in self.block.iter_mut().enumerate() {
+            ui.push_id(index, |ui| i.show(ui));
         }
     }
 }
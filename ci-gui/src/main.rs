@@ -1,5 +1,5 @@
 use ci_gui::LispEditor;
-use ci_lisp::{env::{math::math_environment, prelude::prelude_environment, Environment}, parser_types::SeqParsers, parsers::{CIIntermediateTokenizer, CINewReplParser, CIReplEvaluator, CIStreamingLexer}};
+use ci_lisp::{env::{float::float_environment, math::math_environment, prelude::prelude_environment, string::string_environment, Environment}, limits::{Limits, DEFAULT_MAX_DEPTH, DEFAULT_MAX_STEPS}, parser_types::SeqParsers, parsers::{CIIntermediateTokenizer, CINewReplParser, CIReplEvaluator, CIStreamingLexer, Strategy}};
 use eframe::egui;
 
 use clap::Parser;
@@ -17,7 +17,31 @@ struct Args {
 
     /// Enable built-in math functions. eg. add, sub, inc, dec, etc
     #[arg(long)]
-    math: bool
+    math: bool,
+
+    /// Enable built-in float functions. eg. sqrt, floor, pow, sin, etc
+    #[arg(long)]
+    float: bool,
+
+    /// Enable built-in string functions. eg. str_len, substr, split, upper, etc
+    #[arg(long)]
+    string: bool,
+
+    /// Give up on a cell after this many reduction steps, so a runaway loop can't freeze the window
+    #[arg(long, default_value_t = DEFAULT_MAX_STEPS)]
+    max_steps: usize,
+
+    /// Give up when this many frames are pending at once, eg. in a runaway recursion
+    #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// Give up after this many allocations: closures created, arguments bound, thunks and native function results
+    #[arg(long)]
+    max_allocs: Option<usize>,
+
+    /// When function arguments are evaluated: value (before the call), name (every time they're used) or need (once, when first used)
+    #[arg(long, default_value = "value")]
+    strategy: Strategy
 }
 
 
@@ -32,10 +56,14 @@ fn main() -> eframe::Result {
     let mut env = Environment::default();
     env = prelude_environment(env);
     if args.math { env = math_environment(env); }
+    if args.float { env = float_environment(env); }
+    if args.string { env = string_environment(env); }
+    env = env.with_strategy(args.strategy);
+    env = env.with_limits(Limits { steps: Some(args.max_steps), depth: Some(args.max_depth), allocs: args.max_allocs });
     
     let parser = SeqParsers::new(
         SeqParsers::new(
-            CIStreamingLexer::default(),
+            CIStreamingLexer::new("cell"),
             CIIntermediateTokenizer::default(),
         ),
         SeqParsers::new(
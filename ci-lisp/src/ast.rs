@@ -1,10 +1,13 @@
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
-use crate::{env::Environment, parsers::CIEvalError};
+use im::HashMap;
 
-#[derive(Clone, PartialEq, Eq)]
+use crate::{env::Environment, float::Float, integer::Integer, parsers::CIEvalError, span::Span};
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Value {
-    Int(i32),
+    Int(Integer),
+    Float(Float),
     String(String), // "var"
     Symbol(String), // var
     Ident(String), // 'var
@@ -16,7 +19,8 @@ impl std::fmt::Debug for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Int(i) => write!(f, "{}", i),
-            Value::String(i) => write!(f, "\"{}\"", i),
+            Value::Float(i) => write!(f, "{}", i),
+            Value::String(i) => write!(f, "\"{}\"", escape_string(i)),
             Value::Symbol(i) => write!(f, "{}", i),
             Value::Ident(i) => write!(f, "'{}", i),
             Value::True => write!(f, "t"),
@@ -26,10 +30,27 @@ impl std::fmt::Debug for Value {
     
 }
 
+// the inverse of the lexer's escapes, so printed strings can be read back in
+fn escape_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Int(i) => write!(f, "{}", i),
+            Value::Float(i) => write!(f, "{}", i),
             Value::String(i) => write!(f, "{}", i),
             Value::Symbol(i) => write!(f, "{}", i),
             Value::Ident(i) => write!(f, "'{}", i),
@@ -49,12 +70,18 @@ pub enum Token {
     RCurly,
     LBracket,
     RBracket,
+    // the `'` in `'(...)`, as opposed to an ident like `'a`
+    Quote,
+    Quasiquote,
+    Unquote,
     EOF,
 }
 
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            // strings are shown the way they were written, quoted and escaped
+            Token::Value(a @ Value::String(_)) => write!(f, "{:?}", a),
             Token::Value(a) => write!(f, "{}", a),
             Token::Hash => write!(f, "#"),
             Token::LParen => write!(f, "("),
@@ -63,6 +90,9 @@ impl std::fmt::Display for Token {
             Token::RCurly => write!(f, "}}"),
             Token::LBracket => write!(f, "["),
             Token::RBracket => write!(f, "]"),
+            Token::Quote => write!(f, "'"),
+            Token::Quasiquote => write!(f, "`"),
+            Token::Unquote => write!(f, ","),
             Token::EOF => Ok(()),
         }
     }
@@ -70,9 +100,11 @@ impl std::fmt::Display for Token {
 
 impl Token {
     pub fn guess_value(word: &str) -> Self {
-        if let Ok(word_int) = word.trim().parse::<i32>() {
+        if let Some(word_int) = Integer::parse(word.trim()) {
             Token::Value(Value::Int(word_int))
-        } else if (word.chars().nth(0).unwrap() == '"') && (word.chars().last().unwrap() == '"') {
+        } else if let Some(word_float) = Float::parse(word.trim()) {
+            Token::Value(Value::Float(word_float))
+        } else if word.starts_with('"') && word.ends_with('"') && word.len() > 1 {
             let without_quotes = &word[1..word.len() - 1];
             Token::Value(Value::String(without_quotes.to_string()))
         } else if word.chars().nth(0).unwrap() == '\'' {
@@ -98,18 +130,43 @@ pub enum IntermediateToken {
     RCurly(i32),
     LBracket(i32),
     RBracket(i32),
+    Quote,
+    Quasiquote,
+    Unquote,
     AstNode(AstNode),
     EOF,
 }
 
+pub type NativeFn = Rc<dyn Fn(AstNode) -> Result<AstNode, CIEvalError>>;
+pub type NativeMutEnvFn = Rc<dyn Fn(AstNode, Environment) -> Result<(AstNode, Environment), CIEvalError>>;
+pub type SpecialFn = Rc<dyn Fn(Rc<AstNode>, Environment) -> Result<SpecialResult, CIEvalError>>;
+
+// What a special form does with the unevaluated argument it was given
+pub enum SpecialResult {
+    Value(AstNode),
+    // evaluate this in place of the whole application, as a tail call
+    Eval(Rc<AstNode>, Environment),
+    // evaluate this, then run the quoted code it comes out as in place of the whole application.
+    // Unlike `Eval`, definitions the code makes stick around
+    EvalQuoted(Rc<AstNode>, Environment),
+    // evaluate this, and come out as a special form of the handler, see `try`
+    Try(Rc<AstNode>, Environment),
+}
+
 #[derive(Clone)]
 pub enum Function {
-    Native(Rc<dyn Fn(AstNode) -> Result<AstNode, CIEvalError>>),
-    NativeMutEnv(Rc<dyn Fn(AstNode, Environment) -> Result<(AstNode, Environment), CIEvalError>>),
+    Native(NativeFn),
+    NativeMutEnv(NativeMutEnvFn),
+    // receives its argument unevaluated, along with the caller's environment
+    Special(SpecialFn),
+    // rewrites calls to it before evaluation, see `CIMacroExpander`. Holds the expander function
+    Macro(Rc<AstNode>),
     User {
         varname: String,
-        body: Box<AstNode>,
+        body: Rc<AstNode>,
         doc: Option<String>,
+        // what `def`, `defs` or `letrec` bound it to first, for backtraces
+        name: Option<String>,
         env: Environment
     },
 }
@@ -119,8 +176,10 @@ impl std::fmt::Debug for Function {
         match self {
             Function::Native(_) => write!(f, "<native fn>"),
             Function::NativeMutEnv(_) => write!(f, "<native fn>"),
+            Function::Special(_) => write!(f, "<special form>"),
+            Function::Macro(_) => write!(f, "<macro>"),
             // Function::User { varname: _, body: _, env: _ } => write!(f, "{:?}", self), // this overflows the stack when it tries to render the body of a recursive function
-            Function::User { varname, body: _, doc: _, env: _ } => write!(f, "<user fn {varname}>")
+            Function::User { varname, body: _, doc: _, name: _, env: _ } => write!(f, "<user fn {varname}>")
         }
     }
 }
@@ -130,24 +189,56 @@ impl std::fmt::Display for Function {
         match self {
             Function::Native(_) => write!(f, "<native fn>"),
             Function::NativeMutEnv(_) => write!(f, "<native fn>"),
+            Function::Special(_) => write!(f, "<special form>"),
+            Function::Macro(_) => write!(f, "<macro>"),
             // Function::User { varname, body, env: _ } => write!(f, "λ{} -> {}", varname, body),
-            Function::User { varname, body: _, doc: _, env: _ } => write!(f, "<user fn {varname}>")
+            Function::User { varname, body: _, doc: _, name: _, env: _ } => write!(f, "<user fn {varname}>")
         }
     }
 }
 
+// The code for an argument and where to evaluate it, for when it's needed
+pub struct Thunk {
+    pub code: Rc<AstNode>,
+    pub env: Environment,
+    // under call-by-need, the value once it's been worked out
+    pub value: RefCell<Option<AstNode>>,
+}
+
+impl Thunk {
+    pub fn new(code: Rc<AstNode>, env: Environment) -> Self {
+        Self { code, env, value: RefCell::new(None) }
+    }
+}
+
 #[derive(Clone)]
 pub enum AstNode {
     Value(Value),
     Par {
-        car: Box<AstNode>,
-        cdr: Box<AstNode>
+        car: Rc<AstNode>,
+        cdr: Rc<AstNode>
     },
     Lambda {
         varname: String,
-        body: Box<AstNode>,
+        body: Rc<AstNode>,
     },
-    Function(Function)
+    Function(Function),
+    // a native pair; lists are chains of these ending in nil
+    Cons {
+        car: Rc<AstNode>,
+        cdr: Rc<AstNode>,
+    },
+    // persistent map, written `#{'key value ...}`
+    Map(HashMap<Value, AstNode>),
+    // code as a value, written `'(...)`; evaluates to itself
+    Quote(Rc<AstNode>),
+    // an argument that hasn't been evaluated yet, under call-by-name and call-by-need
+    Thunk(Rc<Thunk>),
+    // where in the source the wrapped node came from; evaluation strips this
+    Spanned {
+        span: Span,
+        node: Rc<AstNode>,
+    }
 }
 
 // this is necessary for std::mem::take to work
@@ -157,6 +248,40 @@ impl Default for AstNode {
     }
 }
 
+impl AstNode {
+    // Move out the children only this node holds on to that have children of their own,
+    // leaving nil in their place
+    fn take_unique_children(&mut self, children: &mut Vec<AstNode>) {
+        let mut take = |child: &mut Rc<AstNode>| {
+            if let Some(node @ (AstNode::Par { car: _, cdr: _ } | AstNode::Cons { car: _, cdr: _ } | AstNode::Lambda { varname: _, body: _ }
+                | AstNode::Quote(_) | AstNode::Spanned { span: _, node: _ })) = Rc::get_mut(child) {
+                children.push(std::mem::take(node));
+            }
+        };
+
+        match self {
+            AstNode::Par { car, cdr } | AstNode::Cons { car, cdr } => {
+                take(car);
+                take(cdr);
+            }
+            AstNode::Lambda { varname: _, body: node } | AstNode::Quote(node) | AstNode::Spanned { span: _, node } => take(node),
+            _ => (),
+        }
+    }
+}
+
+// Long lists and deeply nested code are dropped one node at a time, rather than by recursing into them
+impl Drop for AstNode {
+    fn drop(&mut self) {
+        let mut children = Vec::new();
+        self.take_unique_children(&mut children);
+
+        while let Some(mut node) = children.pop() {
+            node.take_unique_children(&mut children);
+        }
+    }
+}
+
 impl std::fmt::Debug for AstNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -164,6 +289,11 @@ impl std::fmt::Debug for AstNode {
             AstNode::Par { car, cdr } => write!(f, "({} {})", car, cdr),
             AstNode::Lambda { varname, body } => write!(f, "(fn '{} {})", varname, body),
             AstNode::Function(function) => write!(f, "{}", function),
+            AstNode::Cons { car: _, cdr: _ } => self.fmt_cons(f, |f, node| write!(f, "{:?}", node)),
+            AstNode::Map(map) => Self::fmt_map(map, f, |f, node| write!(f, "{:?}", node)),
+            AstNode::Quote(code) => write!(f, "'{:?}", code),
+            AstNode::Thunk(_) => write!(f, "<thunk>"),
+            AstNode::Spanned { span: _, node } => write!(f, "{:?}", node),
         }
     }
 }
@@ -175,25 +305,147 @@ impl std::fmt::Display for AstNode {
             AstNode::Par { car, cdr } => write!(f, "({} {})", car, cdr),
             AstNode::Lambda { varname, body } => write!(f, "(fn '{} {})", varname, body),
             AstNode::Function(function) => write!(f, "{}", function),
+            AstNode::Cons { car: _, cdr: _ } => self.fmt_cons(f, |f, node| write!(f, "{}", node)),
+            AstNode::Map(map) => Self::fmt_map(map, f, |f, node| write!(f, "{}", node)),
+            AstNode::Quote(code) => write!(f, "'{}", code),
+            AstNode::Thunk(_) => write!(f, "<thunk>"),
+            AstNode::Spanned { span: _, node } => write!(f, "{}", node),
         }
     }
 }
 
 impl AstNode {
+    // proper lists print as `[1 2 3]`, anything else as `{car cons cdr}`
+    fn fmt_cons(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        fmt_item: impl Fn(&mut std::fmt::Formatter<'_>, &AstNode) -> std::fmt::Result,
+    ) -> std::fmt::Result {
+        let mut items = Vec::new();
+        let mut rest = self;
+        while let AstNode::Cons { car, cdr } = rest {
+            items.push(car.as_ref());
+            rest = cdr.as_ref();
+        }
+
+        match rest {
+            AstNode::Value(Value::Nil) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    fmt_item(f, item)?;
+                }
+                write!(f, "]")
+            }
+            _ => {
+                for item in items.iter() {
+                    write!(f, "{{")?;
+                    fmt_item(f, item)?;
+                    write!(f, " cons ")?;
+                }
+                fmt_item(f, rest)?;
+                write!(f, "{}", "}".repeat(items.len()))
+            }
+        }
+    }
+
+    // entries in a stable order, so printing the same map twice gives the same text
+    pub fn sorted_entries(map: &HashMap<Value, AstNode>) -> Vec<(&Value, &AstNode)> {
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort_by_cached_key(|(k, _)| format!("{k:?}"));
+        entries
+    }
+
+    fn fmt_map(
+        map: &HashMap<Value, AstNode>,
+        f: &mut std::fmt::Formatter<'_>,
+        fmt_item: impl Fn(&mut std::fmt::Formatter<'_>, &AstNode) -> std::fmt::Result,
+    ) -> std::fmt::Result {
+        write!(f, "#{{")?;
+        for (i, (k, v)) in Self::sorted_entries(map).into_iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{k:?} ")?;
+            fmt_item(f, v)?;
+        }
+        write!(f, "}}")
+    }
+
+    pub fn spanned(self, span: Span) -> AstNode {
+        AstNode::Spanned { span, node: Rc::new(self) }
+    }
+
+    // A piece of quoted code as a value. Forms are quoted, while literals
+    // like numbers and strings already evaluate to themselves, so stay as they are
+    pub fn data(code: &Rc<AstNode>) -> AstNode {
+        match code.unspanned() {
+            AstNode::Par { car: _, cdr: _ } | AstNode::Lambda { varname: _, body: _ } | AstNode::Value(Value::Symbol(_)) => {
+                AstNode::Quote(code.clone())
+            }
+            other => other.clone(),
+        }
+    }
+
+    // the inverse of `data`
+    pub fn code(&self) -> Rc<AstNode> {
+        match self {
+            AstNode::Quote(code) => code.clone(),
+            other => Rc::new(other.clone()),
+        }
+    }
+
+    // look through any source location wrappers
+    pub fn unspanned(&self) -> &AstNode {
+        match self {
+            AstNode::Spanned { span: _, node } => node.unspanned(),
+            other => other,
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            AstNode::Spanned { span, node: _ } => Some(span),
+            _ => None,
+        }
+    }
+
     pub fn help(&self, env: Environment) -> Result<(), CIEvalError> {
         match self {
             AstNode::Value(Value::Int(i)) => {
                 println!("**Type:** Int");
                 println!("**Value:** {i:?}");
             }
+            AstNode::Value(Value::Float(i)) => {
+                println!("**Type:** Float");
+                println!("**Value:** {i:?}");
+            }
             AstNode::Value(Value::String(s)) => {
                 println!("**Type:** String");
                 println!("**Value:** {s:?}");
             }
+            AstNode::Cons { car: _, cdr: _ } => {
+                println!("**Type:** Pair");
+                println!("**Value:** {self:?}");
+            }
+            AstNode::Map(_) => {
+                println!("**Type:** Map");
+                println!("**Value:** {self:?}");
+            }
+            AstNode::Quote(_) => {
+                println!("**Type:** Code");
+                println!("**Value:** {self:?}");
+            }
+            AstNode::Thunk(thunk) => {
+                println!("**Type:** Thunk (not evaluated yet)");
+                println!("**Code:** {}", thunk.code);
+            }
             AstNode::Value(Value::True) => println!("**Value:** t"),
             AstNode::Value(Value::Nil) =>  println!("**Value:** nil"),
 
-            AstNode::Function(Function::User {varname, body, doc, env: _}) => {
+            AstNode::Function(Function::User {varname, body, doc, name: _, env: _}) => {
                 if let Some(desc) = doc {
                     println!("**Description**:");
                     println!("{}\n",
@@ -212,17 +464,25 @@ impl AstNode {
             AstNode::Function(Function::Native(_)) | AstNode::Function(Function::NativeMutEnv(_)) => {
                 println!("Native Function");
             }
+            AstNode::Function(Function::Special(_)) => {
+                println!("Special Form (its argument is only evaluated if needed)");
+            }
+            AstNode::Function(Function::Macro(expander)) => {
+                println!("Macro (expanded before evaluation)\n");
+                expander.help(env.clone())?;
+            }
 
             AstNode::Value(Value::Symbol(_)) => unreachable!(), // these would have already been evaluated by now
             AstNode::Par { car: _, cdr: _ } => unreachable!(),
             AstNode::Lambda {varname: _, body: _} => unreachable!(),
+            AstNode::Spanned { span: _, node } => node.help(env)?,
 
             AstNode::Value(Value::Ident(i)) => {
-                let val = env.get(&i)
+                let val = env.get(i)
                     .ok_or(CIEvalError::UnknownSymbol(i.clone()))?;
 
                 match val {
-                    AstNode::Function(Function::User {varname: _, body: _, doc: _, env: _}) => {
+                    AstNode::Function(Function::User {varname: _, body: _, doc: _, name: _, env: _}) => {
                         println!("**Function:** `{}`\n", &i);
                         val.help(env.clone())?;
                     }
@@ -234,3 +494,32 @@ impl AstNode {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{AstNode, Value};
+
+    #[test]
+    fn dropping_a_long_list_doesnt_overflow_the_stack() {
+        let list = (0..1_000_000i64).fold(AstNode::Value(Value::Nil), |cdr, i| AstNode::Cons {
+            car: Rc::new(AstNode::Value(Value::Int(i.into()))),
+            cdr: Rc::new(cdr),
+        });
+        drop(list);
+    }
+
+    #[test]
+    fn dropping_a_list_keeps_shared_tails() {
+        let tail = Rc::new((0..1_000_000i64).fold(AstNode::Value(Value::Nil), |cdr, i| AstNode::Cons {
+            car: Rc::new(AstNode::Value(Value::Int(i.into()))),
+            cdr: Rc::new(cdr),
+        }));
+        drop(AstNode::Cons { car: Rc::new(AstNode::Value(Value::Nil)), cdr: tail.clone() });
+        let AstNode::Cons { car: _, cdr } = tail.as_ref() else {
+            panic!("{tail}");
+        };
+        assert!(matches!(cdr.as_ref(), AstNode::Cons { car: _, cdr: _ }));
+    }
+}
@@ -0,0 +1,157 @@
+use std::{collections::HashSet, rc::Rc};
+
+use crate::{ast::{AstNode, Value}, parsers::CIEvalError};
+
+// The symbol that marks a lambda in the quoted form, `(fn 'x (fn 'y x))` is `'(λ (λ 1))`
+pub const LAMBDA: &str = "λ";
+
+// A lambda term with its parameters replaced by how many lambdas out they were bound,
+// so terms that only differ in their parameter names come out the same
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeBruijn {
+    // bound by the lambda this many lambdas further out, 0 is the closest
+    Var(usize),
+    Free(String),
+    // numbers, strings and the like, which aren't variables at all
+    Const(Value),
+    Lam(Rc<DeBruijn>),
+    App(Rc<DeBruijn>, Rc<DeBruijn>),
+}
+
+impl std::fmt::Display for DeBruijn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeBruijn::Var(i) => write!(f, "{i}"),
+            DeBruijn::Free(name) => write!(f, "{name}"),
+            DeBruijn::Const(value @ Value::Int(_)) => write!(f, "'{value}"),
+            DeBruijn::Const(value) => write!(f, "{value}"),
+            DeBruijn::Lam(body) => write!(f, "({LAMBDA} {body})"),
+            DeBruijn::App(func, arg) => write!(f, "({func} {arg})"),
+        }
+    }
+}
+
+impl DeBruijn {
+    pub fn from_term(term: &AstNode) -> Result<DeBruijn, CIEvalError> {
+        fn convert(term: &AstNode, bound: &mut Vec<String>) -> Result<DeBruijn, CIEvalError> {
+            match term.unspanned() {
+                AstNode::Value(Value::Symbol(s)) => Ok(match bound.iter().rev().position(|v| v == s) {
+                    Some(i) => DeBruijn::Var(i),
+                    None => DeBruijn::Free(s.clone()),
+                }),
+                AstNode::Value(value) => Ok(DeBruijn::Const(value.clone())),
+                AstNode::Par { car, cdr } => Ok(DeBruijn::App(Rc::new(convert(car, bound)?), Rc::new(convert(cdr, bound)?))),
+                AstNode::Lambda { varname, body } => {
+                    bound.push(varname.clone());
+                    let body = convert(body, bound);
+                    bound.pop();
+                    Ok(DeBruijn::Lam(Rc::new(body?)))
+                }
+                other => Err(CIEvalError::UnexpectedValue(Box::new(other.clone()))),
+            }
+        }
+
+        convert(term, &mut Vec::new())
+    }
+
+    // Back to a term, naming the parameters `x1`, `x2`, ... from the outside in
+    pub fn to_term(&self) -> Result<AstNode, CIEvalError> {
+        fn convert(term: &DeBruijn, names: &mut Vec<String>, free: &HashSet<String>) -> Result<AstNode, CIEvalError> {
+            match term {
+                DeBruijn::Var(i) => match names.len().checked_sub(i + 1) {
+                    Some(level) => Ok(AstNode::Value(Value::Symbol(names[level].clone()))),
+                    None => Err(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(Value::Int((*i as i64).into()))))),
+                },
+                DeBruijn::Free(name) => Ok(AstNode::Value(Value::Symbol(name.clone()))),
+                DeBruijn::Const(value) => Ok(AstNode::Value(value.clone())),
+                DeBruijn::App(func, arg) => Ok(AstNode::Par {
+                    car: Rc::new(convert(func, names, free)?),
+                    cdr: Rc::new(convert(arg, names, free)?),
+                }),
+                DeBruijn::Lam(body) => {
+                    let varname = (names.len() + 1..)
+                        .map(|i| format!("x{i}"))
+                        .find(|name| !free.contains(name))
+                        .unwrap();
+                    names.push(varname.clone());
+                    let body = convert(body, names, free);
+                    names.pop();
+                    Ok(AstNode::Lambda { varname, body: Rc::new(body?) })
+                }
+            }
+        }
+
+        convert(self, &mut Vec::new(), &self.free_names())
+    }
+
+    fn free_names(&self) -> HashSet<String> {
+        match self {
+            DeBruijn::Free(name) => HashSet::from([name.clone()]),
+            DeBruijn::Lam(body) => body.free_names(),
+            DeBruijn::App(func, arg) => func.free_names().union(&arg.free_names()).cloned().collect(),
+            DeBruijn::Var(_) | DeBruijn::Const(_) => HashSet::new(),
+        }
+    }
+
+    // As ci-lisp code: `(λ body)` for lambdas and ints for variables, so int constants get quoted, `'(λ ((add 0) '1))`
+    pub fn to_code(&self) -> AstNode {
+        match self {
+            DeBruijn::Var(i) => AstNode::Value(Value::Int((*i as i64).into())),
+            DeBruijn::Free(name) => AstNode::Value(Value::Symbol(name.clone())),
+            DeBruijn::Const(value @ Value::Int(_)) => AstNode::Quote(Rc::new(AstNode::Value(value.clone()))),
+            DeBruijn::Const(value) => AstNode::Value(value.clone()),
+            DeBruijn::Lam(body) => AstNode::Par {
+                car: Rc::new(AstNode::Value(Value::Symbol(LAMBDA.to_string()))),
+                cdr: Rc::new(body.to_code()),
+            },
+            DeBruijn::App(func, arg) => AstNode::Par { car: Rc::new(func.to_code()), cdr: Rc::new(arg.to_code()) },
+        }
+    }
+
+    pub fn from_code(code: &AstNode) -> Result<DeBruijn, CIEvalError> {
+        match code.unspanned() {
+            AstNode::Value(Value::Int(i)) if !i.is_negative() => Ok(DeBruijn::Var(i.to_usize()?)),
+            AstNode::Value(Value::Symbol(name)) => Ok(DeBruijn::Free(name.clone())),
+            AstNode::Value(value @ (Value::Float(_) | Value::String(_) | Value::Ident(_) | Value::True | Value::Nil)) => {
+                Ok(DeBruijn::Const(value.clone()))
+            }
+            AstNode::Quote(value) => match value.unspanned() {
+                AstNode::Value(value) => Ok(DeBruijn::Const(value.clone())),
+                _ => Err(CIEvalError::UnexpectedValue(Box::new(code.clone()))),
+            },
+            AstNode::Par { car, cdr } => match car.unspanned() {
+                AstNode::Value(Value::Symbol(s)) if s == LAMBDA => Ok(DeBruijn::Lam(Rc::new(Self::from_code(cdr)?))),
+                _ => Ok(DeBruijn::App(Rc::new(Self::from_code(car)?), Rc::new(Self::from_code(cdr)?))),
+            },
+            other => Err(CIEvalError::UnexpectedValue(Box::new(other.clone()))),
+        }
+    }
+}
+
+// Whether `a` and `b` are the same term up to the names of their parameters
+pub fn alpha_eq(a: &AstNode, b: &AstNode) -> Result<bool, CIEvalError> {
+    Ok(DeBruijn::from_term(a)? == DeBruijn::from_term(b)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_lib;
+
+    #[test]
+    fn to_de_bruijn_counts_lambdas() {
+        assert_eq!(eval_lib("(to_de_bruijn '(fn 'x (fn 'y x)))"), "'(λ (λ 1))");
+        assert_eq!(eval_lib("(to_de_bruijn '(fn 'x (fn 'y y)))"), "'(λ (λ 0))");
+    }
+
+    #[test]
+    fn from_de_bruijn_round_trips() {
+        let term = "'(fn 'a (fn 'b (b a)))";
+        assert_eq!(eval_lib(&format!("((alpha_eq (from_de_bruijn (to_de_bruijn {term}))) {term})")), "t");
+    }
+
+    #[test]
+    fn alpha_eq_ignores_parameter_names_only() {
+        assert_eq!(eval_lib("((alpha_eq const) '(fn 'p (fn 'q p)))"), "t");
+        assert_eq!(eval_lib("((alpha_eq '(fn 'x (fn 'y x))) '(fn 'x (fn 'y y)))"), "nil");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{ast::{AstNode, Value}, env::{math::{as_f64, bool_node, float_op}, Environment}, float::Float, integer::Integer, native_fn, parsers::CIEvalError};
+
+fn float_fn(f: fn(f64) -> f64) -> AstNode {
+    native_fn!(
+        (AstNode::Value(a)), {
+            match as_f64(a) {
+                Some(a) => Ok(AstNode::Value(Value::Float(Float(f(a))))),
+                None => Err(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(a.clone()))))
+            }
+        }
+    )
+}
+
+// rounding functions give back an Int, since the result is a whole number anyway
+fn rounding_fn(f: fn(f64) -> f64) -> AstNode {
+    native_fn!(
+        (AstNode::Value(a)), {
+            match a {
+                Value::Int(_) => Ok(AstNode::Value(a.clone())),
+                Value::Float(x) => Integer::from_f64(f(x.0))
+                    .map(|i| AstNode::Value(Value::Int(i)))
+                    .ok_or(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(a.clone())))),
+                _ => Err(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(a.clone()))))
+            }
+        }
+    )
+}
+
+pub fn float_environment(env: Environment) -> Environment {
+    let env = env.insert("pi", AstNode::Value(Value::Float(Float(std::f64::consts::PI))));
+
+    let env = env.insert("is_float", native_fn!(
+        (a), {
+            Ok(bool_node(matches!(a, AstNode::Value(Value::Float(_)))))
+        }
+    ));
+
+    let env = env.insert("to_float", float_fn(|a| a));
+
+    let env = env.insert("sqrt", float_fn(f64::sqrt));
+    let env = env.insert("exp", float_fn(f64::exp));
+    let env = env.insert("ln", float_fn(f64::ln));
+    let env = env.insert("sin", float_fn(f64::sin));
+    let env = env.insert("cos", float_fn(f64::cos));
+    let env = env.insert("tan", float_fn(f64::tan));
+    let env = env.insert("asin", float_fn(f64::asin));
+    let env = env.insert("acos", float_fn(f64::acos));
+    let env = env.insert("atan", float_fn(f64::atan));
+
+    let env = env.insert("floor", rounding_fn(f64::floor));
+    let env = env.insert("ceil", rounding_fn(f64::ceil));
+    let env = env.insert("round", rounding_fn(f64::round));
+
+    // "divide `a` by `b`", always a Float
+    let env = env.insert("float_div", native_fn!(
+        (AstNode::Value(b), AstNode::Value(a)), {
+            Ok(AstNode::Value(float_op(a, b, |a, b| a / b)?))
+        }
+    ));
+
+    // "raise `b` to the power `e`", exact when both are Ints and `e` isn't negative
+    env.insert("pow", native_fn!(
+        (AstNode::Value(e), AstNode::Value(b)), {
+            match (&b, &e) {
+                (Value::Int(b), Value::Int(e)) if !e.is_negative() => {
+                    let e = u32::try_from(e.to_i64()?)
+                        .map_err(|_| CIEvalError::Overflow(e.to_string()))?;
+                    Ok(AstNode::Value(Value::Int(b.pow(e))))
+                }
+                _ => Ok(AstNode::Value(float_op(b, e, f64::powf)?))
+            }
+        }
+    ))
+}
@@ -1,18 +1,59 @@
-use crate::{ast::{AstNode, Value}, env::Environment, native_fn};
+use crate::{ast::{AstNode, Value}, env::Environment, float::Float, integer::Integer, native_fn, parsers::CIEvalError};
+
+pub fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(i.to_f64()),
+        Value::Float(f) => Some(f.0),
+        _ => None
+    }
+}
+
+pub fn float_op(a: &Value, b: &Value, op: fn(f64, f64) -> f64) -> Result<Value, CIEvalError> {
+    match (as_f64(a), as_f64(b)) {
+        (Some(x), Some(y)) => Ok(Value::Float(Float(op(x, y)))),
+        (None, _) => Err(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(a.clone())))),
+        (_, None) => Err(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(b.clone())))),
+    }
+}
+
+// Int with Int stays exact, anything involving a Float is promoted to Float
+pub fn numeric_op(a: &Value, b: &Value, int_op: fn(&Integer, &Integer) -> Integer, op: fn(f64, f64) -> f64) -> Result<Value, CIEvalError> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(Value::Int(int_op(a, b))),
+        _ => float_op(a, b, op)
+    }
+}
+
+pub fn numeric_lt(a: &Value, b: &Value) -> Result<bool, CIEvalError> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(a < b),
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(a), Some(b)) => Ok(a < b),
+            (None, _) => Err(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(a.clone())))),
+            (_, None) => Err(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(b.clone())))),
+        }
+    }
+}
+
+pub fn bool_node(b: bool) -> AstNode {
+    match b {
+        true => AstNode::Value(Value::True),
+        false => AstNode::Value(Value::Nil)
+    }
+}
 
 pub fn math_environment(env: Environment) -> Environment {
     let env = env.insert("inc", native_fn!(
-        (AstNode::Value(Value::Int(a))), {
-            Ok(AstNode::Value(Value::Int(a + 1)))
+        (AstNode::Value(a)), {
+            Ok(AstNode::Value(numeric_op(a, &Value::Int(Integer::from(1)), |a, b| a + b, |a, b| a + b)?))
         }
     ));
     let env = env.insert("dec", native_fn!(
-        (AstNode::Value(Value::Int(a))), {
-            Ok(AstNode::Value(Value::Int(a - 1)))
+        (AstNode::Value(a)), {
+            Ok(AstNode::Value(numeric_op(a, &Value::Int(Integer::from(1)), |a, b| a - b, |a, b| a - b)?))
         }
     ));
 
-    #[allow(unreachable_patterns)]
     let env = env.insert("is_int", native_fn!(
         (a), {
             let res = matches!(a, AstNode::Value(Value::Int(_)));
@@ -25,39 +66,35 @@ pub fn math_environment(env: Environment) -> Environment {
 
     let env = env.insert("eq", native_fn!(
         (AstNode::Value(a), AstNode::Value(b)), {
-            if a == b {
-               Ok(AstNode::Value(Value::True))
-            } else {
-               Ok(AstNode::Value(Value::Nil))
+            match (&a, &b) {
+                (Value::Int(_), Value::Int(_)) => Ok(bool_node(a == b)),
+                _ => match (as_f64(a), as_f64(b)) {
+                    (Some(x), Some(y)) => Ok(bool_node(x == y)),
+                    _ => Ok(bool_node(a == b))
+                }
             }
         }
     ));
 
     let env = env.insert("lt", native_fn!(
-        (AstNode::Value(Value::Int(b)), AstNode::Value(Value::Int(a))), {
-            if a < b {
-                Ok(AstNode::Value(Value::True))
-            } else {
-                Ok(AstNode::Value(Value::Nil))
-            }
+        (AstNode::Value(b), AstNode::Value(a)), {
+            Ok(bool_node(numeric_lt(a, b)?))
         }
     ));
 
     let env = env.insert("builtin__int_add", native_fn!(
-        (AstNode::Value(Value::Int(a)), AstNode::Value(Value::Int(b))), {
-            Ok(AstNode::Value(Value::Int(a + b)))
+        (AstNode::Value(a), AstNode::Value(b)), {
+            Ok(AstNode::Value(numeric_op(a, b, |a, b| a + b, |a, b| a + b)?))
         }
     ));
     let env = env.insert("builtin__int_mul", native_fn!(
-        (AstNode::Value(Value::Int(a)), AstNode::Value(Value::Int(b))), {
-            Ok(AstNode::Value(Value::Int(a * b)))
+        (AstNode::Value(a), AstNode::Value(b)), {
+            Ok(AstNode::Value(numeric_op(a, b, |a, b| a * b, |a, b| a * b)?))
         }
     ));
-    let env = env.insert("builtin__int_sub", native_fn!(
-        (AstNode::Value(Value::Int(b)), AstNode::Value(Value::Int(a))), {
-            Ok(AstNode::Value(Value::Int(a - b)))
+    env.insert("builtin__int_sub", native_fn!(
+        (AstNode::Value(b), AstNode::Value(a)), {
+            Ok(AstNode::Value(numeric_op(a, b, |a, b| a - b, |a, b| a - b)?))
         }
-    ));
-
-    env
+    ))
 }
@@ -1,27 +1,161 @@
+use std::{collections::HashSet, rc::Rc};
+
 use im::HashMap;
-use crate::{ast::AstNode, parser_types::{CIParserError, Parser}};
+use crate::{ast::{AstNode, Function}, limits::{Budget, Limits}, parser_types::{CIParserError, Parser}, parsers::Strategy};
 
 pub mod math;
+pub mod float;
+pub mod string;
 pub mod prelude;
 
+#[derive(Clone, Debug)]
+enum Binding {
+    Value(AstNode),
+    // one of a group of definitions that can all see each other
+    Rec(Rc<RecGroup>),
+}
+
+// Functions in a group keep the environment they were created in, and get the
+// group added to it whenever they're looked up. That way they can call each
+// other without the environment having to refer to itself.
+#[derive(Debug)]
+struct RecGroup {
+    defs: HashMap<String, AstNode>,
+}
+
+// A name bound on top of the hash map, in front of the ones bound before it.
+// Calls bind their parameter this way, since adding it to the hash map would
+// copy part of the map for every call still waiting on a result
+#[derive(Debug)]
+struct Local {
+    name: String,
+    binding: Binding,
+    next: Option<Rc<Local>>,
+}
+
 // Environment is cheap to clone thanks to im::HashMap;
 #[derive(Clone, Default, Debug)]
 pub struct Environment {
-    bindings: HashMap<String, AstNode>
+    bindings: HashMap<String, Binding>,
+    // bound after `bindings`, so they shadow it
+    locals: Option<Rc<Local>>,
+    // shared by every environment derived from this one
+    budget: Rc<Budget>,
+    strategy: Strategy
 }
 
 impl Environment {
     pub fn new(bindings: HashMap<String, AstNode>) -> Environment {
-        Self {bindings}
+        Self {
+            bindings: bindings.into_iter().map(|(k, v)| (k, Binding::Value(v))).collect(),
+            locals: None,
+            budget: Rc::default(),
+            strategy: Strategy::default()
+        }
+    }
+
+    // evaluating in this environment, or any derived from it, is held to `limits`
+    pub fn with_limits(&self, limits: Limits) -> Environment {
+        Self { budget: Rc::new(Budget::new(limits)), ..self.clone() }
+    }
+
+    pub fn budget(&self) -> Rc<Budget> {
+        self.budget.clone()
+    }
+
+    // evaluating in this environment, or any derived from it, passes arguments the `strategy` way
+    pub fn with_strategy(&self, strategy: Strategy) -> Environment {
+        Self { strategy, ..self.clone() }
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
     }
 
     // now this returns a new one instead of modifying the old one  
     pub fn insert(&self, cmd: &str, node: AstNode) -> Environment {
-        Self::new(self.bindings.update(cmd.to_string(), node))
+        match self.locals {
+            // it has to shadow the locals too
+            Some(_) => self.bind(cmd, node),
+            None => Self { bindings: self.bindings.update(cmd.to_string(), Binding::Value(node)), ..self.clone() },
+        }
+    }
+
+    // Like `insert`, for a function's parameter. Binding it only takes a small allocation,
+    // however many bindings there are already
+    pub fn bind(&self, name: &str, node: AstNode) -> Environment {
+        self.with_local(name.to_string(), Binding::Value(node))
+    }
+
+    fn with_local(&self, name: String, binding: Binding) -> Environment {
+        Self { locals: Some(Rc::new(Local { name, binding, next: self.locals.clone() })), ..self.clone() }
+    }
+
+    // Bind all of `defs` at once, so each of them can refer to itself and the others.
+    // Functions that don't have a name yet get the one they're bound to
+    pub fn insert_rec(&self, defs: HashMap<String, AstNode>) -> Environment {
+        let defs = defs.into_iter()
+            .map(|(key, def)| match &def {
+                AstNode::Function(Function::User { varname, body, doc, name: None, env }) => {
+                    let name = Some(key.clone());
+                    (key, AstNode::Function(Function::User { varname: varname.clone(), body: body.clone(), doc: doc.clone(), name, env: env.clone() }))
+                }
+                _ => (key, def),
+            })
+            .collect();
+
+        self.with_group(&Rc::new(RecGroup { defs }))
     }
 
-    pub fn get(&self, key: &str) -> Option<&AstNode> {
-        self.bindings.get(key)
+    fn with_group(&self, group: &Rc<RecGroup>) -> Environment {
+        if self.locals.is_some() {
+            return self.with_local_group(group);
+        }
+
+        let mut bindings = self.bindings.clone();
+        for name in group.defs.keys() {
+            bindings.insert(name.clone(), Binding::Rec(group.clone()));
+        }
+        Self { bindings, ..self.clone() }
+    }
+
+    // the group in front of the other bindings, without copying any of the hash map
+    fn with_local_group(&self, group: &Rc<RecGroup>) -> Environment {
+        group.defs.keys()
+            .fold(self.clone(), |env, name| env.with_local(name.clone(), Binding::Rec(group.clone())))
+    }
+
+    fn locals(&self) -> impl Iterator<Item = &Local> {
+        std::iter::successors(self.locals.as_deref(), |local| local.next.as_deref())
+    }
+
+    // every name bound here, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        let mut seen = HashSet::new();
+        self.locals().map(|local| &local.name)
+            .chain(self.bindings.keys())
+            .filter(move |name| seen.insert(*name))
+    }
+
+    pub fn get(&self, key: &str) -> Option<AstNode> {
+        let binding = match self.locals().find(|local| local.name == key) {
+            Some(local) => &local.binding,
+            None => self.bindings.get(key)?,
+        };
+
+        match binding {
+            Binding::Value(node) => Some(node.clone()),
+            Binding::Rec(group) => match group.defs.get(key)? {
+                AstNode::Function(Function::User { varname, body, doc, name, env }) => Some(AstNode::Function(Function::User {
+                    varname: varname.clone(),
+                    body: body.clone(),
+                    doc: doc.clone(),
+                    name: name.clone(),
+                    env: env.with_local_group(group),
+                })),
+                other => Some(other.clone()),
+            }
+        }
     }
 }
 
@@ -1,39 +1,116 @@
 use std::{fs, rc::Rc};
 
-use crate::{ast::{AstNode, Function, Value}, env::Environment, native_fn, parser_types::Parser, parsers::{CIEvalError, CIFileEvaluator, CIFullFileParser}};
+use im::HashMap;
+
+use crate::{ast::{AstNode, Function, SpecialResult, Value}, de_bruijn::{alpha_eq, DeBruijn}, env::{math::bool_node, Environment}, native_fn, parser_types::{Parser, SeqParsers}, parsers::{CIEvalError, CIFileEvaluator, CIFullFileParser, CIIntermediateTokenizer, CILexer, CIMacroExpander, CINewFileParser}, ski::{from_ski, to_ski}};
+
+// everything except nil counts as true
+pub fn is_truthy(node: &AstNode) -> bool {
+    !matches!(node, AstNode::Value(Value::Nil))
+}
+
+fn special_fn(f: impl Fn(Rc<AstNode>, Environment) -> Result<SpecialResult, CIEvalError> + 'static) -> AstNode {
+    AstNode::Function(Function::Special(Rc::new(f)))
+}
+
+// `#{'name value ...}` as the names and values to bind
+fn rec_defs(defs: &AstNode) -> Result<HashMap<String, AstNode>, CIEvalError> {
+    let AstNode::Map(map) = defs else {
+        return Err(CIEvalError::UnexpectedValue(Box::new(defs.clone())));
+    };
+
+    map.iter()
+        .map(|(name, value)| match name {
+            Value::Ident(name) => Ok((name.clone(), value.clone())),
+            other => Err(CIEvalError::UnexpectedValue(Box::new(AstNode::Value(other.clone())))),
+        })
+        .collect()
+}
+
+// the code inside a quote, without its source location
+fn quoted(node: &AstNode) -> Option<&AstNode> {
+    match node {
+        AstNode::Quote(code) => Some(code.unspanned()),
+        _ => None,
+    }
+}
+
+// A quoted term, or the definition of a function written in ci-lisp as one
+fn lambda_term(node: &AstNode) -> Result<Rc<AstNode>, CIEvalError> {
+    match node {
+        AstNode::Quote(code) => Ok(code.clone()),
+        AstNode::Function(Function::User { varname, body, doc: _, name: _, env: _ }) => Ok(Rc::new(AstNode::Lambda { varname: varname.clone(), body: body.clone() })),
+        other => Err(CIEvalError::UnexpectedValue(Box::new(other.clone()))),
+    }
+}
+
+// a proper list of `items`, ending in nil
+pub fn list_node(items: impl DoubleEndedIterator<Item = AstNode>) -> AstNode {
+    items.rev().fold(AstNode::Value(Value::Nil), |cdr, car| {
+        AstNode::Cons { car: Rc::new(car), cdr: Rc::new(cdr) }
+    })
+}
 
 pub fn prelude_environment(env: Environment) -> Environment {
-    let env = env.insert(
-        "if",
-        AstNode::Function(Function::Native(Rc::new(
-            move |cond_node: AstNode| {
-                match cond_node {
-                    AstNode::Value(Value::Nil) => {
-                        Ok(AstNode::Function(Function::Native(Rc::new(
-                            move |_on_true_node: AstNode| {
-                                Ok(AstNode::Function(Function::Native(Rc::new(
-                                    move |on_false_node: AstNode| {
-                                        Ok(on_false_node.clone())
-                                    },
-                                ))))
-                            },
-                        ))))
-                    }
-                    _ => {
-                        Ok(AstNode::Function(Function::Native(Rc::new(
-                            move |on_true_node: AstNode| {
-                                Ok(AstNode::Function(Function::Native(Rc::new(
-                                    move |_on_false_node: AstNode| {
-                                        Ok(on_true_node.clone())
-                                    },
-                                ))))
-                            },
-                        ))))
-                    }
-                }
-            },
-        ))),
+    // `(((if cond) then) else)` only evaluates the branch it takes
+    let if_form = native_fn!(
+        (cond), {
+            Ok(match is_truthy(cond) {
+                true => special_fn(|then, env| Ok(SpecialResult::Value(
+                    special_fn(move |_, _| Ok(SpecialResult::Eval(then.clone(), env.clone())))
+                ))),
+                false => special_fn(|_, _| Ok(SpecialResult::Value(
+                    special_fn(|otherwise, env| Ok(SpecialResult::Eval(otherwise, env)))
+                ))),
+            })
+        }
     );
+    // `cond` expands to `builtin__if`, so it keeps working if a library rebinds `if`
+    let env = env.insert("builtin__if", if_form.clone());
+    let env = env.insert("if", if_form);
+
+    // `((when cond) body)` is nil unless `cond` holds
+    let env = env.insert("when", native_fn!(
+        (cond), {
+            let cond = cond.clone();
+            Ok(special_fn(move |body, env| Ok(match is_truthy(&cond) {
+                true => SpecialResult::Eval(body, env),
+                false => SpecialResult::Value(AstNode::Value(Value::Nil)),
+            })))
+        }
+    ));
+
+    // `((and a) b)` only evaluates `b` when `a` holds, and `((or a) b)` only when it doesn't
+    let env = env.insert("and", native_fn!(
+        (a), {
+            let a = a.clone();
+            Ok(special_fn(move |b, env| Ok(match is_truthy(&a) {
+                true => SpecialResult::Eval(b, env),
+                false => SpecialResult::Value(a.clone()),
+            })))
+        }
+    ));
+
+    let env = env.insert("or", native_fn!(
+        (a), {
+            let a = a.clone();
+            Ok(special_fn(move |b, env| Ok(match is_truthy(&a) {
+                true => SpecialResult::Value(a.clone()),
+                false => SpecialResult::Eval(b, env),
+            })))
+        }
+    ));
+
+    // `(error value)` stops evaluating, unless a `try` around it catches the value
+    let env = env.insert("error", native_fn!(
+        (value), {
+            Err(CIEvalError::User(Box::new(value.clone())))
+        }
+    ));
+
+    // `((try body) handler)` is `body`, or `(handler err)` if evaluating it failed.
+    // `err` is a map, see `CIEvalError::data`. The evaluator does the catching, see `CIFileEvaluator::catch`
+    let env = env.insert("try", special_fn(|body, env| Ok(SpecialResult::Try(body, env))));
 
     let env = env.insert(
         "inspect_env",
@@ -52,24 +129,253 @@ pub fn prelude_environment(env: Environment) -> Environment {
         })))
     );
 
+    // `{a cons b}`, the same argument order as the church-encoded pairs in lib/church.ci
+    let cons = native_fn!(
+        (cdr, car), {
+            Ok(AstNode::Cons { car: Rc::new(car.clone()), cdr: Rc::new(cdr.clone()) })
+        }
+    );
+    // list literals expand to `builtin__cons`, so they keep working if a library rebinds `cons`
+    let env = env.insert("builtin__cons", cons.clone());
+    let env = env.insert("cons", cons);
+
+    // these also take apart quoted applications, `(car '(f x))` is `'f`
+    let env = env.insert("car", native_fn!(
+        (pair), {
+            match (&pair, quoted(pair)) {
+                (AstNode::Cons { car, cdr: _ }, _) => Ok(car.as_ref().clone()),
+                (_, Some(AstNode::Par { car, cdr: _ })) => Ok(AstNode::data(car)),
+                _ => Err(CIEvalError::UnexpectedValue(Box::new(pair.clone())))
+            }
+        }
+    ));
+
+    let env = env.insert("cdr", native_fn!(
+        (pair), {
+            match (&pair, quoted(pair)) {
+                (AstNode::Cons { car: _, cdr }, _) => Ok(cdr.as_ref().clone()),
+                (_, Some(AstNode::Par { car: _, cdr })) => Ok(AstNode::data(cdr)),
+                _ => Err(CIEvalError::UnexpectedValue(Box::new(pair.clone())))
+            }
+        }
+    ));
+
+    let env = env.insert("nil?", native_fn!(
+        (a), {
+            Ok(bool_node(matches!(a, AstNode::Value(Value::Nil))))
+        }
+    ));
+
+    let env = env.insert("is_pair", native_fn!(
+        (a), {
+            Ok(bool_node(matches!(a, AstNode::Cons { car: _, cdr: _ })))
+        }
+    ));
+
+    let env = env.insert("is_map", native_fn!(
+        (a), {
+            Ok(bool_node(matches!(a, AstNode::Map(_))))
+        }
+    ));
+
+    let env = env.insert("is_par", native_fn!(
+        (a), {
+            Ok(bool_node(matches!(quoted(a), Some(AstNode::Par { car: _, cdr: _ }))))
+        }
+    ));
+
+    let env = env.insert("is_lambda", native_fn!(
+        (a), {
+            Ok(bool_node(matches!(quoted(a), Some(AstNode::Lambda { varname: _, body: _ }))))
+        }
+    ));
+
+    let env = env.insert("lambda_var", native_fn!(
+        (a), {
+            match quoted(a) {
+                Some(AstNode::Lambda { varname, body: _ }) => Ok(AstNode::Value(Value::Ident(varname.clone()))),
+                _ => Err(CIEvalError::UnexpectedValue(Box::new(a.clone())))
+            }
+        }
+    ));
+
+    let env = env.insert("lambda_body", native_fn!(
+        (a), {
+            match quoted(a) {
+                Some(AstNode::Lambda { varname: _, body }) => Ok(AstNode::data(body)),
+                _ => Err(CIEvalError::UnexpectedValue(Box::new(a.clone())))
+            }
+        }
+    ));
+
+    // `((make_par 'f) 'x)` is `'(f x)`; quasiquotes expand to `builtin__make_par`
+    let make_par = native_fn!(
+        (car, cdr), {
+            Ok(AstNode::Quote(Rc::new(AstNode::Par { car: car.code(), cdr: cdr.code() })))
+        }
+    );
+    let env = env.insert("builtin__make_par", make_par.clone());
+    let env = env.insert("make_par", make_par);
+
+    // `((make_lambda 'x) 'x)` is `'(fn 'x x)`
+    let make_lambda = native_fn!(
+        (AstNode::Value(Value::Ident(varname)), body), {
+            Ok(AstNode::Quote(Rc::new(AstNode::Lambda { varname: varname.clone(), body: body.code() })))
+        }
+    );
+    let env = env.insert("builtin__make_lambda", make_lambda.clone());
+    let env = env.insert("make_lambda", make_lambda);
+
+    // Run quoted code in the current environment. Like `include`, definitions it makes stick around.
+    // The code runs in place of the call, so code that calls `eval` again doesn't nest any deeper
+    let env = env.insert("eval", special_fn(|code, env| Ok(SpecialResult::EvalQuoted(code, env))));
+
+    // `((defmacro expander) 'name)`. The expander gets each call quoted, `'((name a) b)`,
+    // and returns the code to run instead. Files use their macros as soon as they're defined
+    let env = env.insert(
+        "defmacro",
+        AstNode::Function(Function::Native(Rc::new(|expander: AstNode| {
+            Ok(AstNode::Function(Function::NativeMutEnv(Rc::new(move |name: AstNode, env1: Environment| {
+                match (&expander, &name) {
+                    (AstNode::Function(_), AstNode::Value(Value::Ident(macro_name))) => {
+                        let expander = AstNode::Function(Function::Macro(Rc::new(expander.clone())));
+                        Ok((AstNode::Value(Value::Nil), env1.insert(macro_name, expander)))
+                    },
+                    (AstNode::Function(_), other) => Err(CIEvalError::UnexpectedValue(Box::new(other.clone()))),
+                    (other, _) => Err(CIEvalError::UnexpectedValue(Box::new(other.clone())))
+                }
+            }))))
+        })))
+    );
+
+    // `(macroexpand '(-> x f g))` is the code the call turns into
+    let env = env.insert("macroexpand", AstNode::Function(Function::NativeMutEnv(Rc::new(|code: AstNode, env: Environment| {
+        let expanded = CIMacroExpander::expand(&code.code(), &env)?;
+        Ok((AstNode::data(&expanded), env))
+    }))));
+
+    // `(call_args '(((f a) b) c))` is `['a 'b 'c]`, handy for taking apart a macro call
+    let env = env.insert("call_args", native_fn!(
+        (call), {
+            let mut args = Vec::new();
+            let mut head = quoted(call);
+            while let Some(AstNode::Par { car, cdr }) = head {
+                args.push(AstNode::data(cdr));
+                head = Some(car.unspanned());
+            }
+            Ok(list_node(args.into_iter().rev()))
+        }
+    ));
+
+    // `(to_de_bruijn '(fn 'x (fn 'y x)))` is `'(λ (λ 1))`
+    let env = env.insert("to_de_bruijn", native_fn!(
+        (term), {
+            let term = lambda_term(term)?;
+            Ok(AstNode::data(&Rc::new(DeBruijn::from_term(&term)?.to_code())))
+        }
+    ));
+
+    let env = env.insert("from_de_bruijn", native_fn!(
+        (term), {
+            let code = lambda_term(term)?;
+            Ok(AstNode::data(&Rc::new(DeBruijn::from_code(&code)?.to_term()?)))
+        }
+    ));
+
+    // `((alpha_eq '(fn 'x x)) id)` is t, parameter names aside they're the same function
+    let env = env.insert("alpha_eq", native_fn!(
+        (a, b), {
+            let (a, b) = (lambda_term(a)?, lambda_term(b)?);
+            Ok(bool_node(alpha_eq(&a, &b)?))
+        }
+    ));
+
+    // `(to_ski '(fn 'x (fn 'x x)))` is `'(const id)`, in terms of `lib/ext_math.ci`'s combinators
+    let env = env.insert("to_ski", native_fn!(
+        (term), {
+            let term = lambda_term(term)?;
+            Ok(AstNode::data(&to_ski(&term)))
+        }
+    ));
+
+    let env = env.insert("from_ski", native_fn!(
+        (term), {
+            let term = lambda_term(term)?;
+            Ok(AstNode::data(&Rc::new(from_ski(&term)?)))
+        }
+    ));
+
+    // `(((assoc key) value) map)`; map literals expand to `builtin__assoc`
+    let assoc = native_fn!(
+        (AstNode::Value(key), value, AstNode::Map(map)), {
+            Ok(AstNode::Map(map.update(key.clone(), value.clone())))
+        }
+    );
+    let env = env.insert("builtin__assoc", assoc.clone());
+    let env = env.insert("assoc", assoc);
+
+    let env = env.insert("dissoc", native_fn!(
+        (AstNode::Value(key), AstNode::Map(map)), {
+            Ok(AstNode::Map(map.without(key)))
+        }
+    ));
+
+    let env = env.insert("get", native_fn!(
+        (AstNode::Value(key), AstNode::Map(map)), {
+            Ok(map.get(key).cloned().unwrap_or_default())
+        }
+    ));
+
+    let env = env.insert("contains", native_fn!(
+        (AstNode::Value(key), AstNode::Map(map)), {
+            Ok(bool_node(map.contains_key(key)))
+        }
+    ));
+
+    let env = env.insert("keys", native_fn!(
+        (AstNode::Map(map)), {
+            Ok(list_node(AstNode::sorted_entries(map).into_iter().map(|(k, _)| AstNode::Value(k.clone()))))
+        }
+    ));
+
+    let env = env.insert("vals", native_fn!(
+        (AstNode::Map(map)), {
+            Ok(list_node(AstNode::sorted_entries(map).into_iter().map(|(_, v)| v.clone())))
+        }
+    ));
+
     let env = env.insert(
         "def",
         AstNode::Function(Function::Native(Rc::new(|body: AstNode| {
             Ok(AstNode::Function(Function::NativeMutEnv(Rc::new(move |name: AstNode, env1: Environment| {
-                match name {
+                match &name {
+                    // the definition can see itself, so functions can recurse by name
                     AstNode::Value(Value::Ident(fn_name)) => {
-                        Ok((AstNode::Value(Value::Nil), env1.insert(&fn_name, body.clone())))
+                        Ok((AstNode::Value(Value::Nil), env1.insert_rec(HashMap::unit(fn_name.clone(), body.clone()))))
                     },
-                    other => Err(CIEvalError::UnexpectedValue(Box::new(other)))
+                    other => Err(CIEvalError::UnexpectedValue(Box::new(other.clone())))
                 }
             }))))
         })))
     );
 
+    // `(defs #{'even? (fn ...) 'odd? (fn ...)})` defines several functions that can call each other
+    let env = env.insert("defs", AstNode::Function(Function::NativeMutEnv(Rc::new(|defs: AstNode, env: Environment| {
+        Ok((AstNode::Value(Value::Nil), env.insert_rec(rec_defs(&defs)?)))
+    }))));
+
+    // `(letrec #{'f (fn ...) 'g (fn ...)} body)`, like `defs` but only visible in `body`
+    let env = env.insert("letrec", native_fn!(
+        (defs), {
+            let defs = rec_defs(defs)?;
+            Ok(special_fn(move |body, env| Ok(SpecialResult::Eval(body, env.insert_rec(defs.clone())))))
+        }
+    ));
+
     let env = env.insert("doc", native_fn!(
-        (AstNode::Value(Value::String(doc)), AstNode::Function(Function::User {varname, body, doc: _, env})), {
+        (AstNode::Value(Value::String(doc)), AstNode::Function(Function::User {varname, body, doc: _, name, env})), {
             Ok(AstNode::Function(Function::User {
-                varname, body, doc: Some(doc.to_string()), env
+                varname: varname.clone(), body: body.clone(), doc: Some(doc.to_string()), name: name.clone(), env: env.clone()
             }))
         }
     ));
@@ -79,18 +385,24 @@ pub fn prelude_environment(env: Environment) -> Environment {
         Ok((AstNode::Value(Value::Nil), env))
     }))));
 
-    let env = env.insert(
+    env.insert(
         "include",
         AstNode::Function(Function::NativeMutEnv(Rc::new(|arg: AstNode, env: Environment| {
-            let filename = match arg {
-                AstNode::Value(Value::String(s)) => s,
-                other => return Err(CIEvalError::UnexpectedValue(Box::new(other)))
+            let filename = match &arg {
+                AstNode::Value(Value::String(s)) => s.clone(),
+                other => return Err(CIEvalError::UnexpectedValue(Box::new(other.clone())))
             };
 
             let source = fs::read_to_string(&filename)
-                .map_err(|_| CIEvalError::NoSuchFile(filename))?;
+                .map_err(|_| CIEvalError::NoSuchFile(filename.clone()))?;
 
-            let parser = CIFullFileParser::default();
+            let parser = SeqParsers::new(
+                CIFullFileParser::new(
+                    SeqParsers::new(CILexer::new(&filename), CIIntermediateTokenizer::default()),
+                    CINewFileParser::default()
+                ),
+                CIMacroExpander::new(env.clone())
+            );
                 let parsed_nodes = match parser.parse(source.chars().collect()) {
                     Ok(a) => a,
                     Err(e) => return Err(CIEvalError::FileParseError(Box::new(e)))
@@ -103,7 +415,5 @@ pub fn prelude_environment(env: Environment) -> Environment {
 
                 Ok((AstNode::Value(Value::Nil), evaluator.take_env()))
         })))
-    );
-
-    env
+    )
 }
@@ -0,0 +1,132 @@
+use crate::{ast::{AstNode, Value}, env::{prelude::list_node, Environment}, integer::Integer, native_fn};
+
+// All indices and lengths count chars, not bytes, so slicing never lands inside a character.
+
+fn string_node(s: impl Into<String>) -> AstNode {
+    AstNode::Value(Value::String(s.into()))
+}
+
+fn int_node(i: usize) -> AstNode {
+    AstNode::Value(Value::Int(Integer::from(i as i64)))
+}
+
+// negative indices are clamped to 0, and ones too large for a usize to the end of any string
+fn index(i: &Integer) -> usize {
+    match i.is_negative() {
+        true => 0,
+        false => i.to_usize().unwrap_or(usize::MAX),
+    }
+}
+
+fn string_fn(f: fn(&str) -> String) -> AstNode {
+    native_fn!(
+        (AstNode::Value(Value::String(s))), {
+            Ok(string_node(f(s)))
+        }
+    )
+}
+
+pub fn string_environment(env: Environment) -> Environment {
+    let env = env.insert("str_len", native_fn!(
+        (AstNode::Value(Value::String(s))), {
+            Ok(int_node(s.chars().count()))
+        }
+    ));
+
+    // "the chars of `s` from `start` up to, but not including, `end`"; out of range indices are clamped
+    let env = env.insert("substr", native_fn!(
+        (AstNode::Value(Value::Int(start)), AstNode::Value(Value::Int(end)), AstNode::Value(Value::String(s))), {
+            let start = index(start);
+            let end = index(end);
+            Ok(string_node(s.chars().skip(start).take(end.saturating_sub(start)).collect::<String>()))
+        }
+    ));
+
+    // `{"a,b,c" split ","}`; an empty separator splits into single chars
+    let env = env.insert("split", native_fn!(
+        (AstNode::Value(Value::String(sep)), AstNode::Value(Value::String(s))), {
+            if sep.is_empty() {
+                return Ok(list_node(s.chars().map(string_node)));
+            }
+            Ok(list_node(s.split(sep.as_str()).map(string_node).collect::<Vec<_>>().into_iter()))
+        }
+    ));
+
+    // `{s index_of needle}`, the char index of the first match or nil
+    let env = env.insert("index_of", native_fn!(
+        (AstNode::Value(Value::String(needle)), AstNode::Value(Value::String(s))), {
+            Ok(match s.find(needle.as_str()) {
+                Some(i) => int_node(s[..i].chars().count()),
+                None => AstNode::Value(Value::Nil),
+            })
+        }
+    ));
+
+    // `(((replace from) to) s)` replaces every occurrence of `from`
+    let env = env.insert("replace", native_fn!(
+        (AstNode::Value(Value::String(from)), AstNode::Value(Value::String(to)), AstNode::Value(Value::String(s))), {
+            Ok(string_node(s.replace(from.as_str(), to)))
+        }
+    ));
+
+    let env = env.insert("upper", string_fn(str::to_uppercase));
+    let env = env.insert("lower", string_fn(str::to_lowercase));
+    let env = env.insert("trim", string_fn(|s| s.trim().to_string()));
+
+    // anything as it would be printed, strings are left alone
+    let env = env.insert("to_string", native_fn!(
+        (a), {
+            Ok(string_node(a.to_string()))
+        }
+    ));
+
+    // nil when `s` isn't a whole number
+    let env = env.insert("parse_int", native_fn!(
+        (AstNode::Value(Value::String(s))), {
+            Ok(match Integer::parse(s.trim()) {
+                Some(i) => AstNode::Value(Value::Int(i)),
+                None => AstNode::Value(Value::Nil),
+            })
+        }
+    ));
+
+    // `{s char_at 0}`, the char at an index as a string of length one, or nil past the end
+    let env = env.insert("char_at", native_fn!(
+        (AstNode::Value(Value::Int(i)), AstNode::Value(Value::String(s))), {
+            Ok(match s.chars().nth(index(i)) {
+                Some(c) => string_node(c),
+                None => AstNode::Value(Value::Nil),
+            })
+        }
+    ));
+
+    env.insert("chars", native_fn!(
+        (AstNode::Value(Value::String(s))), {
+            Ok(list_node(s.chars().map(string_node)))
+        }
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast::AstNode, env::{prelude::prelude_environment, Environment}, test_util::eval_in};
+
+    use super::string_environment;
+
+    fn eval(source: &str) -> AstNode {
+        eval_in(string_environment(prelude_environment(Environment::default())), &[], source).unwrap()
+    }
+
+    #[test]
+    fn substr_clamps_negative_indices() {
+        assert_eq!(eval(r#"(substr -2 2 "hello")"#).to_string(), "he");
+        assert_eq!(eval(r#"(substr -5 -1 "hello")"#).to_string(), "");
+        assert_eq!(eval(r#"(substr 3 99999999999999999999 "hello")"#).to_string(), "lo");
+    }
+
+    #[test]
+    fn char_at_clamps_negative_indices() {
+        assert_eq!(eval(r#"(char_at -1 "hello")"#).to_string(), "h");
+        assert_eq!(eval(r#"(char_at 99999999999999999999 "hello")"#).to_string(), "nil");
+    }
+}
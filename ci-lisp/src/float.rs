@@ -0,0 +1,44 @@
+// f64 wrapper so `Value` can keep deriving `Eq`. Two floats are the same
+// value when they have the same bits; numeric comparisons live in the math natives.
+#[derive(Clone, Copy)]
+pub struct Float(pub f64);
+
+impl Float {
+    pub fn parse(word: &str) -> Option<Float> {
+        // `inf`, `nan` and friends are valid symbol names, so only accept things that look numeric
+        let numeric = word.chars().any(|c| c.is_ascii_digit())
+            && word.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+
+        match numeric {
+            true => word.parse::<f64>().ok().map(Float),
+            false => None,
+        }
+    }
+}
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Float {}
+
+impl std::hash::Hash for Float {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+
+// always print a decimal point or exponent, so `1.0` doesn't read back as an Int
+impl std::fmt::Display for Float {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl std::fmt::Debug for Float {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
@@ -0,0 +1,149 @@
+use std::{cmp::Ordering, rc::Rc};
+
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use crate::parsers::CIEvalError;
+
+// Arbitrary-precision integer. Anything that fits in an i64 stays `Small`,
+// so everyday arithmetic never touches the heap; results that outgrow it
+// are promoted to `Big`. Keeping that invariant means derived equality and
+// hashing agree with numeric equality.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Integer {
+    Small(i64),
+    Big(Rc<BigInt>),
+}
+
+impl Integer {
+    pub fn parse(word: &str) -> Option<Integer> {
+        if let Ok(i) = word.parse::<i64>() {
+            return Some(Integer::Small(i));
+        }
+
+        let digits = word.strip_prefix(['+', '-']).unwrap_or(word);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        word.parse::<BigInt>().ok().map(Integer::from)
+    }
+
+    fn to_big(&self) -> BigInt {
+        match self {
+            Integer::Small(i) => BigInt::from(*i),
+            Integer::Big(b) => b.as_ref().clone(),
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Integer::Small(i) => *i < 0,
+            Integer::Big(b) => b.sign() == num_bigint::Sign::Minus,
+        }
+    }
+
+    pub fn to_i64(&self) -> Result<i64, CIEvalError> {
+        match self {
+            Integer::Small(i) => Ok(*i),
+            Integer::Big(b) => Err(CIEvalError::Overflow(b.to_string())),
+        }
+    }
+
+    // nearest float; huge values become +/- infinity
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Integer::Small(i) => *i as f64,
+            Integer::Big(b) => b.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    // the integral part of `f`, or None for nan and infinities
+    pub fn from_f64(f: f64) -> Option<Integer> {
+        BigInt::from_f64(f.trunc()).map(Integer::from)
+    }
+
+    pub fn pow(&self, exp: u32) -> Integer {
+        Integer::from(self.to_big().pow(exp))
+    }
+
+    pub fn to_usize(&self) -> Result<usize, CIEvalError> {
+        usize::try_from(self.to_i64()?)
+            .map_err(|_| CIEvalError::Overflow(self.to_string()))
+    }
+}
+
+impl From<i64> for Integer {
+    fn from(i: i64) -> Self {
+        Integer::Small(i)
+    }
+}
+
+impl From<BigInt> for Integer {
+    fn from(b: BigInt) -> Self {
+        match i64::try_from(&b) {
+            Ok(i) => Integer::Small(i),
+            Err(_) => Integer::Big(Rc::new(b)),
+        }
+    }
+}
+
+macro_rules! impl_checked_op {
+    ($trait:ident, $method:ident, $checked:ident) => {
+        impl std::ops::$trait for &Integer {
+            type Output = Integer;
+
+            fn $method(self, rhs: &Integer) -> Integer {
+                match (self, rhs) {
+                    (Integer::Small(a), Integer::Small(b)) => match a.$checked(*b) {
+                        Some(res) => Integer::Small(res),
+                        None => Integer::from(std::ops::$trait::$method(BigInt::from(*a), BigInt::from(*b))),
+                    },
+                    _ => Integer::from(std::ops::$trait::$method(self.to_big(), rhs.to_big())),
+                }
+            }
+        }
+
+        impl std::ops::$trait for Integer {
+            type Output = Integer;
+
+            fn $method(self, rhs: Integer) -> Integer {
+                std::ops::$trait::$method(&self, &rhs)
+            }
+        }
+    };
+}
+
+impl_checked_op!(Add, add, checked_add);
+impl_checked_op!(Sub, sub, checked_sub);
+impl_checked_op!(Mul, mul, checked_mul);
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Integer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Integer::Small(a), Integer::Small(b)) => a.cmp(b),
+            _ => self.to_big().cmp(&other.to_big()),
+        }
+    }
+}
+
+impl std::fmt::Display for Integer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Integer::Small(i) => write!(f, "{}", i),
+            Integer::Big(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+impl std::fmt::Debug for Integer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
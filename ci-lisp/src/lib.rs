@@ -3,3 +3,14 @@ pub mod ast;
 pub mod parser_types;
 pub mod native_fn;
 pub mod env;
+pub mod span;
+pub mod limits;
+pub mod palette;
+pub mod reduce;
+pub mod de_bruijn;
+pub mod ski;
+pub mod integer;
+pub mod float;
+
+#[cfg(test)]
+mod test_util;
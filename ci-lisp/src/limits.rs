@@ -0,0 +1,122 @@
+use std::cell::Cell;
+
+use crate::parsers::CIEvalError;
+
+// How many reduction steps an input gets by default before giving up on it as a runaway loop,
+// about half a minute of evaluating
+pub const DEFAULT_MAX_STEPS: usize = 100_000_000;
+
+// How many pending frames the evaluator allows by default before giving up on a runaway recursion.
+// Reaching it takes a few hundred MB
+pub const DEFAULT_MAX_DEPTH: usize = 1_000_000;
+
+// What evaluating a single top-level form is allowed to use. `None` means unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // reduction steps the evaluator takes
+    pub steps: Option<usize>,
+    // frames pending at once, roughly how deep the recursion goes
+    pub depth: Option<usize>,
+    // allocations the evaluator makes: the closures it creates, the arguments it binds,
+    // the thunks it delays arguments in and the results of native functions
+    pub allocs: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { steps: None, depth: Some(DEFAULT_MAX_DEPTH), allocs: None }
+    }
+}
+
+impl Limits {
+    // for trusted code like preloaded libraries, which shouldn't count against what the user asked for
+    pub fn unlimited() -> Self {
+        Self { steps: None, depth: None, allocs: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Steps,
+    Depth,
+    Allocs,
+}
+
+impl std::fmt::Display for ResourceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceKind::Steps => write!(f, "reduction steps"),
+            ResourceKind::Depth => write!(f, "call depth"),
+            ResourceKind::Allocs => write!(f, "allocations"),
+        }
+    }
+}
+
+// What's been used so far. Lives in the environment, so evaluations started
+// from inside another one (`include`, macro expansion...) draw from the same budget.
+#[derive(Debug, Default)]
+pub struct Budget {
+    limits: Limits,
+    steps: Cell<usize>,
+    allocs: Cell<usize>,
+    // frames pending in the evaluations further out
+    depth: Cell<usize>,
+    nesting: Cell<usize>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Self { limits, ..Default::default() }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    // Start an evaluation. The outermost one gets a fresh budget
+    pub fn run(&self) -> Run<'_> {
+        if self.nesting.get() == 0 {
+            self.steps.set(0);
+            self.allocs.set(0);
+        }
+        self.nesting.set(self.nesting.get() + 1);
+        Run { budget: self, base_depth: self.depth.get() }
+    }
+}
+
+pub struct Run<'a> {
+    budget: &'a Budget,
+    base_depth: usize,
+}
+
+impl Run<'_> {
+    // one more step taken, with `pending` frames left on this evaluation's stack
+    pub fn step(&self, pending: usize) -> Result<(), CIEvalError> {
+        let budget = self.budget;
+        budget.steps.set(budget.steps.get() + 1);
+        check(ResourceKind::Steps, budget.steps.get(), budget.limits.steps)?;
+
+        budget.depth.set(self.base_depth + pending);
+        check(ResourceKind::Depth, budget.depth.get(), budget.limits.depth)
+    }
+
+    pub fn alloc(&self) -> Result<(), CIEvalError> {
+        let budget = self.budget;
+        budget.allocs.set(budget.allocs.get() + 1);
+        check(ResourceKind::Allocs, budget.allocs.get(), budget.limits.allocs)
+    }
+}
+
+impl Drop for Run<'_> {
+    fn drop(&mut self) {
+        self.budget.nesting.set(self.budget.nesting.get() - 1);
+        self.budget.depth.set(self.base_depth);
+    }
+}
+
+fn check(kind: ResourceKind, used: usize, limit: Option<usize>) -> Result<(), CIEvalError> {
+    match limit {
+        Some(limit) if used > limit => Err(CIEvalError::ResourceExhausted { kind, limit }),
+        _ => Ok(()),
+    }
+}
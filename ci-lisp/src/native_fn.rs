@@ -1,35 +1,43 @@
 #[macro_export]
 macro_rules! native_fn {
-    (($($arg_pat:pat),*), $body:block) => {
+    (($($arg_pat:pat),*), $body:block) => {{
+        let prev = ();
         $crate::ast::AstNode::Function($crate::ast::Function::Native(std::rc::Rc::new(
-            native_fn!(@curried [$($arg_pat),*] => $body)
+            native_fn!(@curried prev, (), [$($arg_pat),*] => $body)
         )))
-    };
-    
-    (@curried [] => $body:block) => {
+    }};
+
+    (@curried $prev:ident, $acc:pat, [] => $body:block) => {
         move |arg| {
             let _: () = arg;
             $body
         }
     };
-    
-    (@curried [$arg_pat:pat] => $body:block) => {
-        move |arg| {
-            match arg.clone() {
-                $arg_pat => $body,
-                other => Err($crate::parsers::CIEvalError::UnexpectedValue(Box::new(other.clone()))),
+
+    // Every level keeps the raw arguments it has seen so far in `$prev`, and matches
+    // all of them again, so the body can borrow the bindings of every argument.
+    // They're matched inside a `Some` so the fallback arm is still reachable when
+    // every pattern is a plain binding.
+    (@curried $prev:ident, $acc:pat, [$arg_pat:pat] => $body:block) => {
+        move |arg: $crate::ast::AstNode| {
+            match Some((&$prev, &arg)) {
+                Some(($acc, $arg_pat)) => $body,
+                _ => Err($crate::parsers::CIEvalError::UnexpectedValue(Box::new(arg.clone()))),
             }
         }
     };
-    
-    // The one-line fix to your original code:
-    (@curried [$first_pat:pat, $($rest_pats:pat),+] => $body:block) => {
-        move |arg| {
-            match arg.clone() { // <- Add .clone() here
-                $first_pat => Ok($crate::ast::AstNode::Function($crate::ast::Function::Native(std::rc::Rc::new(
-                    native_fn!(@curried [$($rest_pats),*] => $body)
-                )))),
-                other => Err($crate::parsers::CIEvalError::UnexpectedValue(Box::new(other.clone()))),
+
+    (@curried $prev:ident, $acc:pat, [$first_pat:pat, $($rest_pats:pat),+] => $body:block) => {
+        move |arg: $crate::ast::AstNode| {
+            match Some((&$prev, &arg)) {
+                #[allow(unused_variables)]
+                Some(($acc, $first_pat)) => {
+                    let $prev = ($prev.clone(), arg.clone());
+                    Ok($crate::ast::AstNode::Function($crate::ast::Function::Native(std::rc::Rc::new(
+                        native_fn!(@curried $prev, ($acc, $first_pat), [$($rest_pats),*] => $body)
+                    ))))
+                },
+                _ => Err($crate::parsers::CIEvalError::UnexpectedValue(Box::new(arg.clone()))),
             }
         }
     };
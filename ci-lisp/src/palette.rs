@@ -0,0 +1,16 @@
+use crate::ast::{Token, Value};
+
+// How ci-gui and ci-term colour each kind of token, as rgb
+pub fn token_rgb(token: &Token) -> (u8, u8, u8) {
+    match token {
+        Token::Value(Value::Int(_)) | Token::Value(Value::Float(_)) => (200, 150, 255), // purple
+        Token::Value(Value::String(_)) => (255, 200, 100), // orange
+        Token::Value(Value::Symbol(_)) => (100, 200, 255), // blue
+        Token::Value(Value::Ident(_)) => (150, 255, 150), // green
+        Token::Value(Value::True) | Token::Value(Value::Nil) => (173, 216, 230), // light blue
+        _ => (255, 255, 255),
+    }
+}
+
+// symbols that aren't bound to anything
+pub const UNBOUND_RGB: (u8, u8, u8) = (255, 80, 80);
@@ -1,21 +1,31 @@
-use crate::{ast::{AstNode, IntermediateToken, Token}, parsers::{CIEvalError, CILexerError}};
+use crate::{ast::{AstNode, IntermediateToken, Token}, env::Environment, parsers::{CallSite, CIEvalError, CILexerError}, span::{Span, Spanned}};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CIParserError {
     #[error("Missing Opening Parenthesis (level: {0})")]
-    MissingOpenParen(i32),
+    MissingOpenParen(i32, Span),
 
     #[error("Missing Closing Parenthesis (level: {0})")]
-    MissingCloseParen(i32),
+    MissingCloseParen(i32, Span),
     
-    #[error("UnknownToken: {0:?}")]
-    UnknownToken(Box<Token>),
+    #[error("UnknownToken: {:?}", .0.node)]
+    UnknownToken(Box<Spanned<Token>>),
 
-    #[error("UnexpectedToken: {0:?}")]
-    UnexpectedToken(Box<IntermediateToken>),
+    #[error("UnexpectedToken: {:?}", .0.node)]
+    UnexpectedToken(Box<Spanned<IntermediateToken>>),
 
     #[error("Too many parameters in Node: {0:?}")]
-    NodeFull(Vec<AstNode>),
+    NodeFull(Vec<AstNode>, Span),
+
+    #[error("Lambda must take at least one parameter")]
+    NoParameters(Span),
+
+    // the span covers the parameters, there's no body to point at
+    #[error("Lambda has no body")]
+    MissingBody(Span),
+
+    #[error("Map literal has a key without a value")]
+    UnpairedMapKey(Span),
 
     #[error("[Internal] parsing not done")]
     ParsingUnfinished,
@@ -27,6 +37,50 @@ pub enum CIParserError {
     EvalError(#[from] CIEvalError),
 }
 
+impl CIParserError {
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            CIParserError::MissingOpenParen(_, span)
+            | CIParserError::MissingCloseParen(_, span)
+            | CIParserError::NodeFull(_, span)
+            | CIParserError::NoParameters(span)
+            | CIParserError::MissingBody(span)
+            | CIParserError::UnpairedMapKey(span) => Some(span),
+            CIParserError::UnknownToken(tok) => Some(&tok.span),
+            CIParserError::UnexpectedToken(tok) => Some(&tok.span),
+            CIParserError::LexerError(e) => e.span(),
+            CIParserError::EvalError(e) => e.span(),
+            CIParserError::ParsingUnfinished => None,
+        }
+    }
+
+    // Human-readable report, with a snippet of the offending source when we know where it is
+    pub fn render(&self) -> String {
+        match self {
+            CIParserError::EvalError(e) => e.render(),
+            other => match other.span() {
+                Some(span) => span.render("error", &other.to_string()),
+                None => format!("error: {other}"),
+            }
+        }
+    }
+
+    // like `render`, leaving out the backtrace
+    pub fn render_error(&self) -> String {
+        match self {
+            CIParserError::EvalError(e) => e.render_error(),
+            other => other.render(),
+        }
+    }
+
+    pub fn backtrace(&self) -> &[CallSite] {
+        match self {
+            CIParserError::EvalError(e) => e.backtrace(),
+            _ => &[],
+        }
+    }
+}
+
 pub trait Parser {
     type Input;
     type Output;
@@ -34,6 +88,13 @@ pub trait Parser {
     fn parse(&self, tokens: Self::Input) -> Result<Self::Output, CIParserError>;
 }
 
+// Stages that evaluate what they're given, and can tell what's been defined so far
+pub trait HasEnv {
+    fn env(&self) -> Environment;
+    // carry on from `env` instead, eg. to start over or pick up what a file defined
+    fn set_env(&self, env: Environment);
+}
+
 pub trait ParserState {
     type Output;
 
@@ -64,7 +125,7 @@ impl<T: SingleParser> Parser for T {
     type Output = T::Output;
 
     fn parse(&self, tokens: Self::Input) -> Result<Self::Output, CIParserError> {
-        T::single_parse(&self, tokens)
+        T::single_parse(self, tokens)
     }
 }
 
@@ -126,3 +187,17 @@ where
         SeqParsers { a, b }
     }
 }
+
+impl<A, B> HasEnv for SeqParsers<A, B>
+where
+    A: Parser,
+    B: Parser<Input = A::Output> + HasEnv
+{
+    fn env(&self) -> Environment {
+        self.b.env()
+    }
+
+    fn set_env(&self, env: Environment) {
+        self.b.set_env(env)
+    }
+}
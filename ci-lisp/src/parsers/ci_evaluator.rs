@@ -1,6 +1,8 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, rc::Rc};
 
-use crate::{ast::{AstNode, Function, Value}, env::Environment, parser_types::{CIParserError, Parser}};
+use im::HashMap;
+
+use crate::{ast::{AstNode, Function, SpecialResult, Thunk, Value}, env::Environment, limits::{ResourceKind, Run}, parser_types::{CIParserError, HasEnv, Parser}, parsers::CIMacroExpander, span::Span};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CIEvalError {
@@ -13,14 +15,282 @@ pub enum CIEvalError {
     #[error("Unexpected Value: {0:?}")]
     UnexpectedValue(Box<AstNode>),
 
+    #[error("Integer too large: {0}")]
+    Overflow(String),
+
     #[error("Application form is invalid")]
     InvalidApplication,
 
+    #[error("Ran out of {kind} (the limit is {limit})")]
+    ResourceExhausted {
+        kind: ResourceKind,
+        limit: usize
+    },
+
+    #[error("Macro {0} is still expanding after {1} levels, does it expand to itself?")]
+    MacroDepth(String, usize),
+
     #[error("File does not exist: {0}")]
     NoSuchFile(String),
 
+    // the files in the cycle, starting and ending with the same one
+    #[error("Files include each other in a cycle: {}", .0.join(" -> "))]
+    IncludeCycle(Vec<String>),
+
     #[error("Error while parsing file: {0}")]
-    FileParseError(#[from] Box<CIParserError>)
+    FileParseError(#[from] Box<CIParserError>),
+
+    // raised by `error`, carrying whatever value it was given
+    #[error("{0}")]
+    User(Box<AstNode>),
+
+    #[error("{error}")]
+    At {
+        span: Span,
+        error: Box<CIEvalError>
+    },
+
+    // the calls that were in progress when `error` happened, innermost first
+    #[error("{error}")]
+    Traced {
+        trace: Vec<CallSite>,
+        error: Box<CIEvalError>
+    }
+}
+
+// A function call that was in progress when an error happened
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    // the name the function was called by, or its parameter for anonymous ones
+    pub name: String,
+    // where it was called from
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for CallSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.span {
+            Some(span) => write!(f, "in {} (called at {span})", self.name),
+            None => write!(f, "in {}", self.name),
+        }
+    }
+}
+
+impl CIEvalError {
+    // attach a location, unless a more precise one was already attached further in
+    pub fn at(self, span: &Span) -> Self {
+        match self {
+            CIEvalError::At { span: _, error: _ } => self,
+            CIEvalError::Traced { trace, error } => CIEvalError::Traced { trace, error: Box::new(error.at(span)) },
+            other => CIEvalError::At { span: span.clone(), error: Box::new(other) }
+        }
+    }
+
+    // add calls further out to the backtrace
+    pub fn traced(self, calls: Vec<CallSite>) -> Self {
+        match self {
+            CIEvalError::Traced { mut trace, error } => {
+                trace.extend(calls);
+                CIEvalError::Traced { trace, error }
+            }
+            other if calls.is_empty() => other,
+            other => CIEvalError::Traced { trace: calls, error: Box::new(other) }
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            CIEvalError::At { span, error: _ } => Some(span),
+            CIEvalError::Traced { trace: _, error } => error.span(),
+            _ => None
+        }
+    }
+
+    pub fn backtrace(&self) -> &[CallSite] {
+        match self {
+            CIEvalError::Traced { trace, error: _ } => trace,
+            _ => &[]
+        }
+    }
+
+    // `in nth <- in len <- at repl:1`, with runs of the same call counted instead of repeated
+    pub fn render_backtrace(trace: &[CallSite]) -> String {
+        let mut parts: Vec<(String, usize)> = Vec::new();
+        for call in trace {
+            match parts.last_mut() {
+                Some((name, count)) if *name == call.name => *count += 1,
+                _ => parts.push((call.name.clone(), 1)),
+            }
+        }
+
+        let mut parts: Vec<String> = parts.into_iter()
+            .map(|(name, count)| match count {
+                1 => format!("in {name}"),
+                n => format!("in {name} ({n} times)"),
+            })
+            .collect();
+
+        if let Some(span) = trace.last().and_then(|call| call.span.as_ref()) {
+            parts.push(format!("at {}:{}", span.source.name, span.line));
+        }
+
+        format!("backtrace: {}", parts.join(" <- "))
+    }
+
+    // the error underneath any locations and included files it was raised through
+    pub fn root(&self) -> &CIEvalError {
+        match self {
+            CIEvalError::At { span: _, error } => error.root(),
+            CIEvalError::Traced { trace: _, error } => error.root(),
+            CIEvalError::FileParseError(e) => match e.as_ref() {
+                CIParserError::EvalError(e) => e.root(),
+                _ => self,
+            },
+            other => other,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self.root() {
+            CIEvalError::UnknownSymbol(_) => "unknown_symbol",
+            CIEvalError::NonCallable(_) => "non_callable",
+            CIEvalError::UnexpectedValue(_) => "unexpected_value",
+            CIEvalError::Overflow(_) => "overflow",
+            CIEvalError::InvalidApplication => "invalid_application",
+            CIEvalError::ResourceExhausted { kind: _, limit: _ } => "resource_exhausted",
+            CIEvalError::MacroDepth(_, _) => "macro_depth",
+            CIEvalError::NoSuchFile(_) => "no_such_file",
+            CIEvalError::IncludeCycle(_) => "include_cycle",
+            CIEvalError::FileParseError(_) => "parse_error",
+            CIEvalError::User(_) => "user",
+            CIEvalError::At { span: _, error: _ } | CIEvalError::Traced { trace: _, error: _ } => unreachable!(),
+        }
+    }
+
+    // `#{'kind 'unknown_symbol 'message "Unknown symbol: x"}`, what `try` hands to its handler.
+    // Errors raised with `error` also keep their value under `'value`. Their message is only
+    // taken from strings, so re-raising a caught error doesn't keep growing the message
+    pub fn data(&self) -> AstNode {
+        let message = match self.root() {
+            CIEvalError::User(value) => match value.as_ref() {
+                AstNode::Value(Value::String(s)) => s.clone(),
+                _ => "Error raised by user code".to_string(),
+            },
+            other => other.to_string(),
+        };
+
+        let map = HashMap::new()
+            .update(Value::Ident("kind".to_string()), AstNode::Value(Value::Ident(self.kind().to_string())))
+            .update(Value::Ident("message".to_string()), AstNode::Value(Value::String(message)));
+
+        match self.root() {
+            CIEvalError::User(value) => AstNode::Map(map.update(Value::Ident("value".to_string()), value.as_ref().clone())),
+            _ => AstNode::Map(map),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        match self {
+            CIEvalError::Traced { trace, error } => format!("{}\n{}", error.render(), Self::render_backtrace(trace)),
+            other => other.render_error()
+        }
+    }
+
+    // like `render`, leaving out the backtrace
+    pub fn render_error(&self) -> String {
+        match self {
+            CIEvalError::Traced { trace: _, error } => error.render_error(),
+            CIEvalError::At { span, error } => match error.as_ref() {
+                CIEvalError::FileParseError(e) => format!("{}\n{}", e.render(), span.render("note", "while evaluating this")),
+                other => span.render("error", &other.to_string())
+            },
+            CIEvalError::FileParseError(e) => e.render(),
+            other => format!("error: {other}")
+        }
+    }
+}
+
+// When the arguments to user functions get evaluated. Natives always get theirs evaluated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    // before the call, the usual
+    #[default]
+    Value,
+    // every time the parameter is used
+    Name,
+    // the first time the parameter is used, then the value is remembered
+    Need,
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "value" => Ok(Strategy::Value),
+            "name" => Ok(Strategy::Name),
+            "need" => Ok(Strategy::Need),
+            other => Err(format!("unknown strategy {other:?}, expected value, name or need")),
+        }
+    }
+}
+
+// Work left to do once the expression currently being evaluated produces a value
+enum Frame {
+    // the function of an application is known, evaluate its argument next.
+    // `callee` is the code the function came from, for backtraces
+    Arg { cdr: Rc<AstNode>, env: Environment, callee: Rc<AstNode> },
+
+    // the argument is known, apply the function to it
+    Apply { func: AstNode, env: Environment, callee: Rc<AstNode> },
+
+    // a user function body finished, hand the value back to the caller's environment
+    Return { env: Environment, call: Option<Call> },
+
+    // a thunk's value is known, remember it if the strategy says so, then carry on in `env`
+    Force { thunk: Rc<Thunk>, env: Environment },
+
+    // the quoted code given to `eval` is known, run it in `env`
+    Quoted { env: Environment },
+
+    // the body of a `try`, errors raised while this frame is on the stack are handed to its handler
+    Catch { env: Environment },
+
+    // errors raised while this frame is on the stack happened inside `span`
+    Span(Span),
+}
+
+// A user function call in progress, for backtraces
+struct Call {
+    callee: Rc<AstNode>,
+    // the name the function was defined with, if any
+    name: Option<String>,
+    param: String,
+}
+
+impl Call {
+    // the name the function was defined with, even when it's called through a parameter.
+    // Otherwise `nth` for both `(nth 10)` and `((nth 10) xs)`, the parameter for anonymous functions
+    fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        let mut head = self.callee.unspanned();
+        while let AstNode::Par { car, cdr: _ } = head {
+            head = car.unspanned();
+        }
+
+        match head {
+            AstNode::Value(Value::Symbol(name)) => name.clone(),
+            _ => format!("fn '{}", self.param),
+        }
+    }
+}
+
+enum Control {
+    Eval(Rc<AstNode>, Environment),
+    Value(AstNode, Environment),
 }
 
 pub struct CIFileEvaluator {
@@ -37,29 +307,48 @@ impl CIFileEvaluator {
     }
 
     pub fn eval_node(&self, node: &AstNode, env: Environment) -> Result<(AstNode, Environment), CIEvalError> {
-        match node {
-            AstNode::Par { car, cdr } => {
-                let func = self.eval_node(car, env.clone())?.0;
-                let arg = self.eval_node(cdr, env.clone())?.0;
+        let budget = env.budget();
+        let run = budget.run();
+        let strategy = env.strategy();
 
-                match func {
-                    AstNode::Function(Function::Native(f)) => Ok((f(arg)?, env)),
-                    AstNode::Function(Function::NativeMutEnv(f)) => Ok(f(arg, env)?),
+        let mut stack = Vec::new();
+        let mut control = Control::Eval(Rc::new(node.clone()), env);
 
-                    AstNode::Function(Function::User { varname, body, doc: _, env: func_env }) => {
-                        let (res, _) = self.eval_node(&body, func_env.insert(&varname, arg))?;
-                        Ok((res, env))
-                    }
+        loop {
+            let step = match control {
+                Control::Eval(node, env) => Self::eval_step(node, env, &mut stack, &run),
+                Control::Value(value, env) => match stack.pop() {
+                    None => return Ok((value, env)),
+                    Some(frame) => Self::continue_step(frame, value, env, &mut stack, &run, strategy),
+                },
+            };
 
-                    other => Err(CIEvalError::NonCallable(Box::new(other))),
-                }
+            control = match step {
+                Ok(next) => next,
+                Err(e) => Self::catch(e, &mut stack)?,
+            };
+
+            if let Err(e) = run.step(stack.len()) {
+                control = Self::catch(e, &mut stack)?;
+            }
+        }
+    }
+
+    // Take one step into `node`, pushing whatever is left to do onto the stack
+    fn eval_step(node: Rc<AstNode>, env: Environment, stack: &mut Vec<Frame>, run: &Run) -> Result<Control, CIEvalError> {
+        match node.as_ref() {
+            AstNode::Par { car, cdr } => {
+                stack.push(Frame::Arg { cdr: cdr.clone(), env: env.clone(), callee: car.clone() });
+                Ok(Control::Eval(car.clone(), env))
             }
 
             AstNode::Lambda { varname, body } => {
-                Ok((AstNode::Function(Function::User {
+                run.alloc()?;
+                Ok(Control::Value(AstNode::Function(Function::User {
                     varname: varname.clone(),
-                    body: Box::new(*body.clone()),
+                    body: body.clone(),
                     doc: None,
+                    name: None,
                     env: env.clone(),
                 }), env))
             },
@@ -68,11 +357,191 @@ impl CIFileEvaluator {
                 let val = env.get(s)
                     .ok_or(CIEvalError::UnknownSymbol(s.clone()))?;
 
-                self.eval_node(val, env.clone())
+                match &val {
+                    AstNode::Thunk(thunk) => Ok(Self::force(thunk.clone(), env, stack)),
+                    _ => Ok(Control::Eval(Rc::new(val), env)),
+                }
+            }
+
+            AstNode::Spanned { span, node } => {
+                // nothing else is left to do in a span that's directly around this one
+                if matches!(stack.last(), Some(Frame::Span(_))) {
+                    stack.pop();
+                }
+                stack.push(Frame::Span(span.clone()));
+                Ok(Control::Eval(node.clone(), env))
+            }
+
+            _ => Ok(Control::Value(node.as_ref().clone(), env)),
+        }
+    }
+
+    // Feed `value` into the frame that was waiting for it
+    fn continue_step(frame: Frame, value: AstNode, env: Environment, stack: &mut Vec<Frame>, run: &Run, strategy: Strategy) -> Result<Control, CIEvalError> {
+        match frame {
+            // special forms decide for themselves whether to evaluate their argument
+            Frame::Arg { cdr, env, callee } => match &value {
+                AstNode::Function(Function::Special(f)) => match f(cdr, env.clone())? {
+                    SpecialResult::Value(res) => Ok(Control::Value(res, env)),
+                    SpecialResult::Eval(node, node_env) => {
+                        Self::push_return(stack, env, None);
+                        Ok(Control::Eval(node, node_env))
+                    }
+                    SpecialResult::EvalQuoted(node, node_env) => {
+                        stack.push(Frame::Quoted { env });
+                        Ok(Control::Eval(node, node_env))
+                    }
+                    SpecialResult::Try(node, node_env) => {
+                        stack.push(Frame::Catch { env });
+                        Ok(Control::Eval(node, node_env))
+                    }
+                },
+                AstNode::Function(Function::User { varname: _, body: _, doc: _, name: _, env: _ }) if strategy != Strategy::Value => {
+                    let arg = Self::delay(cdr, &env, run)?;
+                    stack.push(Frame::Apply { func: value, env: env.clone(), callee });
+                    Ok(Control::Value(arg, env))
+                }
+                _ => {
+                    stack.push(Frame::Apply { func: value, env: env.clone(), callee });
+                    Ok(Control::Eval(cdr, env))
+                }
+            }
+
+            Frame::Apply { func, env, callee } => match &func {
+                AstNode::Function(Function::Native(f)) => {
+                    run.alloc()?;
+                    Ok(Control::Value(f(value)?, env))
+                }
+                AstNode::Function(Function::NativeMutEnv(f)) => {
+                    run.alloc()?;
+                    let (res, env) = f(value, env)?;
+                    Ok(Control::Value(res, env))
+                }
+
+                AstNode::Function(Function::User { varname, body, doc: _, name, env: func_env }) => {
+                    run.alloc()?;
+                    let body_env = func_env.bind(varname, value);
+                    Self::push_return(stack, env, Some(Call { callee, name: name.clone(), param: varname.clone() }));
+                    Ok(Control::Eval(body.clone(), body_env))
+                }
+
+                _ => Err(CIEvalError::NonCallable(Box::new(func))),
             }
 
-            _ => Ok((node.clone(), env)),
+            Frame::Return { env, call: _ } => Ok(Control::Value(value, env)),
+            Frame::Force { thunk, env } => {
+                if strategy == Strategy::Need {
+                    *thunk.value.borrow_mut() = Some(value.clone());
+                }
+                Ok(Control::Value(value, env))
+            }
+            // no return frame, so definitions the code makes carry on past it
+            Frame::Quoted { env } => {
+                let code = CIMacroExpander::expand(&value.code(), &env)?;
+                Ok(Control::Eval(code, env))
+            }
+            // the body worked out, so the handler is never called
+            Frame::Catch { env } => Ok(Control::Value(Self::special(move |_, _| Ok(SpecialResult::Value(value.clone()))), env)),
+            Frame::Span(_) => Ok(Control::Value(value, env)),
+        }
+    }
+
+    fn special(f: impl Fn(Rc<AstNode>, Environment) -> Result<SpecialResult, CIEvalError> + 'static) -> AstNode {
+        AstNode::Function(Function::Special(Rc::new(f)))
+    }
+
+    // Hand an error to the innermost `try` still evaluating its body, dropping the frames above it.
+    // Running out of steps or allocations can't be caught, there'd be nothing left to run the handler with
+    fn catch(error: CIEvalError, stack: &mut Vec<Frame>) -> Result<Control, CIEvalError> {
+        let catchable = !matches!(error.root(), CIEvalError::ResourceExhausted { kind: ResourceKind::Steps | ResourceKind::Allocs, limit: _ });
+        let Some(at) = stack.iter().rposition(|f| matches!(f, Frame::Catch { env: _ })).filter(|_| catchable) else {
+            return Err(Self::unwind(error, std::mem::take(stack)));
+        };
+
+        let Some(Frame::Catch { env }) = stack.drain(at..).next() else {
+            unreachable!()
+        };
+        let err = Rc::new(error.data());
+        Ok(Control::Value(Self::special(move |handler, env| Ok(SpecialResult::Eval(
+            Rc::new(AstNode::Par { car: handler, cdr: err.clone() }),
+            env
+        ))), env))
+    }
+
+    // An argument for a user function, left unevaluated. Passing a parameter on
+    // passes its thunk along too, so call-by-need still only evaluates it once
+    fn delay(code: Rc<AstNode>, env: &Environment, run: &Run) -> Result<AstNode, CIEvalError> {
+        match code.unspanned() {
+            AstNode::Value(Value::Symbol(s)) => {
+                if let Some(val) = env.get(s) {
+                    return Ok(val);
+                }
+            }
+            AstNode::Par { car: _, cdr: _ } | AstNode::Lambda { varname: _, body: _ } => (),
+            // nothing to gain from delaying what evaluates to itself
+            other => return Ok(other.clone()),
         }
+
+        run.alloc()?;
+        Ok(AstNode::Thunk(Rc::new(Thunk::new(code, env.clone()))))
+    }
+
+    // Evaluate a thunk's code where it came from, unless its value is already known
+    fn force(thunk: Rc<Thunk>, env: Environment, stack: &mut Vec<Frame>) -> Control {
+        if let Some(value) = thunk.value.borrow().clone() {
+            return Control::Value(value, env);
+        }
+
+        let (code, code_env) = (thunk.code.clone(), thunk.env.clone());
+        stack.push(Frame::Force { thunk, env });
+        Control::Eval(code, code_env)
+    }
+
+    // Proper tail calls: when the caller has nothing left to do but return,
+    // the callee can return straight to the caller's caller instead.
+    // Backtraces keep showing the caller then, a loop shows up as the call that started it
+    fn push_return(stack: &mut Vec<Frame>, env: Environment, call: Option<Call>) {
+        let pending = stack.iter().rev().find(|f| !matches!(f, Frame::Span(_)));
+
+        if matches!(pending, Some(Frame::Return { env: _, call: _ })) {
+            while matches!(stack.last(), Some(Frame::Span(_))) {
+                stack.pop();
+            }
+        } else {
+            stack.push(Frame::Return { env, call });
+        }
+    }
+
+    // Attach the innermost source location still on the stack to an error,
+    // along with the function calls that led there
+    fn unwind(error: CIEvalError, stack: Vec<Frame>) -> CIEvalError {
+        let mut span = None;
+        let mut trace = Vec::new();
+
+        for frame in stack.into_iter() {
+            match frame {
+                Frame::Span(s) => span = Some(s),
+                Frame::Return { env: _, call: Some(call) } => trace.push(CallSite { name: call.name(), span: span.clone() }),
+                _ => (),
+            }
+        }
+        trace.reverse();
+
+        let error = match span {
+            Some(span) => error.at(&span),
+            None => error,
+        };
+        error.traced(trace)
+    }
+}
+
+impl HasEnv for CIFileEvaluator {
+    fn env(&self) -> Environment {
+        self.env.borrow().clone()
+    }
+
+    fn set_env(&self, env: Environment) {
+        *self.env.borrow_mut() = env;
     }
 }
 
@@ -92,3 +561,79 @@ impl Parser for CIFileEvaluator {
         Ok(nodes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast::AstNode, limits::{Limits, ResourceKind}, parser_types::CIParserError, parsers::CIEvalError, test_util::{eval_in, eval_lib, full_environment, EXT_MATH}};
+
+    fn eval_limited(limits: Limits, source: &str) -> Result<AstNode, CIParserError> {
+        eval_in(full_environment().with_limits(limits), &[EXT_MATH], source)
+    }
+
+    fn exhausted(result: Result<AstNode, CIParserError>) -> Option<ResourceKind> {
+        match result {
+            Err(CIParserError::EvalError(e)) => match e.root() {
+                CIEvalError::ResourceExhausted { kind, limit: _ } => Some(*kind),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tail_calls_dont_add_depth() {
+        let limits = Limits { steps: None, depth: Some(1000), allocs: None };
+        let looping = "(letrec #{'loop (fn 'n (((if {n eq 0}) 'done) (loop {n sub 1})))} (loop 100000))";
+        assert_eq!(eval_limited(limits, looping).unwrap().to_string(), "'done");
+    }
+
+    #[test]
+    fn letrec_mutual_recursion() {
+        let parity = "#{'even (fn 'n (((if {n eq 0}) t) (odd {n sub 1}))) 'odd (fn 'n (((if {n eq 0}) nil) (even {n sub 1})))}";
+        assert_eq!(eval_lib(&format!("(letrec {parity} (even 10001))")), "nil");
+        assert_eq!(eval_lib(&format!("(letrec {parity} (odd 10001))")), "t");
+    }
+
+    #[test]
+    fn try_gives_the_handler_an_error_map() {
+        assert_eq!(eval_lib("((try (nope 1)) (fn 'e ((get 'kind) e)))"), "'unknown_symbol");
+        assert_eq!(eval_lib("((try (nope 1)) (fn 'e ((get 'message) e)))"), "Unknown symbol: nope");
+        assert_eq!(eval_lib("((try (error #{'code 4})) (fn 'e ((get 'kind) e)))"), "'user");
+        assert_eq!(eval_lib("((try (error #{'code 4})) (fn 'e ((get 'value) e)))"), "#{'code 4}");
+        assert_eq!(eval_lib("((try {1 add 2}) (fn 'e e))"), "3");
+    }
+
+    #[test]
+    fn running_out_of_depth_can_be_caught() {
+        let limits = Limits { steps: None, depth: Some(1000), allocs: None };
+        let down = "(letrec #{'down (fn 'n {1 add (down n)})} (down 0))";
+        assert_eq!(exhausted(eval_limited(limits, down)), Some(ResourceKind::Depth));
+
+        let caught = format!("((try {down}) (fn 'e ((get 'kind) e)))");
+        assert_eq!(eval_limited(limits, &caught).unwrap().to_string(), "'resource_exhausted");
+    }
+
+    #[test]
+    fn running_out_of_steps_or_allocs_cant_be_caught() {
+        let looping = "(letrec #{'loop (fn 'n (loop n))} ((try (loop 0)) (fn 'e e)))";
+
+        let limits = Limits { steps: Some(10_000), ..Limits::default() };
+        assert_eq!(exhausted(eval_limited(limits, looping)), Some(ResourceKind::Steps));
+
+        let limits = Limits { allocs: Some(10_000), ..Limits::default() };
+        assert_eq!(exhausted(eval_limited(limits, looping)), Some(ResourceKind::Allocs));
+    }
+
+    // every pending call keeps its environment alive, so these need it to be small
+    #[test]
+    fn deep_non_tail_recursion() {
+        let down = "(letrec #{'down (fn 'n (((if {n eq 0}) 0) {1 add (down {n sub 1})}))} (down 100000))";
+        assert_eq!(eval_lib(down), "100000");
+    }
+
+    #[test]
+    fn len_and_foldr_on_a_long_list() {
+        assert_eq!(eval_lib("(len ((range 1) 100000))"), "100000");
+        assert_eq!(eval_lib("(sum ((range 1) 100000))"), "5000050000");
+    }
+}
@@ -1,21 +1,37 @@
 use std::cmp::Ordering;
 
-use crate::{ast::{IntermediateToken, Token}, parser_types::{CIParserError, Parser, ParserState}};
+use crate::{ast::{IntermediateToken, Token}, parser_types::{CIParserError, Parser, ParserState}, span::{Span, Spanned}};
 
 #[derive(Default)]
 pub struct CIIntermediateTokenizerState {
-    new_tokens: Vec<IntermediateToken>,
+    new_tokens: Vec<Spanned<IntermediateToken>>,
     cur_paren_level: i32,
+
+    // where each currently unclosed paren was opened
+    open_spans: Vec<Span>,
 }
 
 impl CIIntermediateTokenizerState {
-    fn push_token(&mut self, token: IntermediateToken) {
-        self.new_tokens.push(token)
+    fn push_token(&mut self, token: IntermediateToken, span: Span) {
+        self.new_tokens.push(Spanned::new(token, span))
+    }
+
+    fn open(&mut self, token: IntermediateToken, span: Span) {
+        self.open_spans.push(span.clone());
+        self.push_token(token, span);
+    }
+
+    fn close(&mut self, token: IntermediateToken, span: Span) -> Result<(), CIParserError> {
+        if self.open_spans.pop().is_none() {
+            return Err(CIParserError::MissingOpenParen(self.cur_paren_level - 1, span));
+        }
+        self.push_token(token, span);
+        Ok(())
     }
 }
 
 impl ParserState for CIIntermediateTokenizerState {
-    type Output = Vec<IntermediateToken>;
+    type Output = Vec<Spanned<IntermediateToken>>;
 
     fn take_tokens(self) -> Self::Output {
         self.new_tokens
@@ -26,41 +42,49 @@ impl ParserState for CIIntermediateTokenizerState {
 pub struct CIIntermediateTokenizer {}
 
 impl CIIntermediateTokenizer {
-    fn handle_token(token: Token, state: &mut CIIntermediateTokenizerState) -> Result<(), CIParserError> {
+    fn handle_token(token: Spanned<Token>, state: &mut CIIntermediateTokenizerState) -> Result<(), CIParserError> {
+        let Spanned { node: token, span } = token;
+
         match token {
             Token::LParen => {
                 state.cur_paren_level += 1;
-                state.push_token(IntermediateToken::LParen(state.cur_paren_level));
+                state.open(IntermediateToken::LParen(state.cur_paren_level), span);
             },
             Token::LCurly => {
                 state.cur_paren_level += 1;
-                state.push_token(IntermediateToken::LCurly(state.cur_paren_level));
+                state.open(IntermediateToken::LCurly(state.cur_paren_level), span);
             },
             Token::LBracket => {
                 state.cur_paren_level += 1;
-                state.push_token(IntermediateToken::LBracket(state.cur_paren_level));
+                state.open(IntermediateToken::LBracket(state.cur_paren_level), span);
             }
-            Token::Value(a) => state.push_token(IntermediateToken::Value(a)),
-            Token::Hash => state.push_token(IntermediateToken::Hash),
+            Token::Value(a) => state.push_token(IntermediateToken::Value(a), span),
+            Token::Hash => state.push_token(IntermediateToken::Hash, span),
+            Token::Quote => state.push_token(IntermediateToken::Quote, span),
+            Token::Quasiquote => state.push_token(IntermediateToken::Quasiquote, span),
+            Token::Unquote => state.push_token(IntermediateToken::Unquote, span),
             Token::RParen => {
-                state.push_token(IntermediateToken::RParen(state.cur_paren_level));
+                state.close(IntermediateToken::RParen(state.cur_paren_level), span)?;
                 state.cur_paren_level -= 1;
             },
             Token::RCurly => {
-                state.push_token(IntermediateToken::RCurly(state.cur_paren_level));
+                state.close(IntermediateToken::RCurly(state.cur_paren_level), span)?;
                 state.cur_paren_level -= 1;
             },
             Token::RBracket => {
-                state.push_token(IntermediateToken::RBracket(state.cur_paren_level));
+                state.close(IntermediateToken::RBracket(state.cur_paren_level), span)?;
                 state.cur_paren_level -= 1;
             }
             Token::EOF => {
-                state.push_token(IntermediateToken::EOF);
+                state.push_token(IntermediateToken::EOF, span.clone());
 
                 // check paren levels
                 match state.cur_paren_level.cmp(&0) {
-                    Ordering::Less => return Err(CIParserError::MissingOpenParen(state.cur_paren_level)),
-                    Ordering::Greater => return Err(CIParserError::MissingCloseParen(state.cur_paren_level)),
+                    Ordering::Less => return Err(CIParserError::MissingOpenParen(state.cur_paren_level, span)),
+                    Ordering::Greater => {
+                        let unclosed = state.open_spans.last().cloned().unwrap_or(span);
+                        return Err(CIParserError::MissingCloseParen(state.cur_paren_level, unclosed))
+                    },
                     Ordering::Equal => ()
                 }
             },
@@ -71,8 +95,8 @@ impl CIIntermediateTokenizer {
 }
 
 impl Parser for CIIntermediateTokenizer {
-    type Input = Vec<Token>;
-    type Output = Vec<IntermediateToken>;
+    type Input = Vec<Spanned<Token>>;
+    type Output = Vec<Spanned<IntermediateToken>>;
 
     fn parse(&self, tokens: Self::Input) -> Result<Self::Output, CIParserError> {
         let mut state = CIIntermediateTokenizerState::default();
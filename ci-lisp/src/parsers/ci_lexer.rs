@@ -1,25 +1,126 @@
-use crate::{ast::Token, parser_types::{CIParserError, Parser}};
+use crate::{ast::{Token, Value}, parser_types::{CIParserError, Parser}, span::{Source, Span, SpanTracker, Spanned}};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CILexerError {
     #[error("Unmatched quotes")]
-    UnmatchedQuotes,
+    UnmatchedQuotes(Span),
+    #[error("Invalid escape sequence: {}", .0.text())]
+    InvalidEscape(Span),
+    #[error("Unclosed block comment")]
+    UnclosedComment(Span),
+}
+
+impl CILexerError {
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            CILexerError::UnmatchedQuotes(span) => Some(span),
+            CILexerError::InvalidEscape(span) => Some(span),
+            CILexerError::UnclosedComment(span) => Some(span),
+        }
+    }
+}
+
+pub(crate) enum Comment {
+    // `;` up to the end of the line
+    Line,
+    // `#| ... |#`, which can nest. `prev` is the last char seen, `start` is the opening `#|`
+    Block { depth: usize, prev: char, start: Span },
+}
+
+impl Comment {
+    // feed the next char inside the comment, returns false once the comment is over
+    pub(crate) fn step(&mut self, ch: char) -> bool {
+        match self {
+            Comment::Line => ch != '\n',
+            Comment::Block { depth, prev, start: _ } => {
+                match (*prev, ch) {
+                    // forget the char, so `#|#` and `|#|` aren't counted twice
+                    ('|', '#') => { *depth -= 1; *prev = ' ' }
+                    ('#', '|') => { *depth += 1; *prev = ' ' }
+                    _ => *prev = ch,
+                }
+                *depth > 0
+            }
+        }
+    }
+}
+
+pub(crate) enum Escape {
+    Done(char),
+    Pending,
+    Invalid,
+}
+
+// Decode the chars after a backslash in a string: \" \\ \n \t and \u{1F600}
+pub(crate) fn decode_escape(escape: &str) -> Escape {
+    match escape {
+        "\"" => Escape::Done('"'),
+        "\\" => Escape::Done('\\'),
+        "n" => Escape::Done('\n'),
+        "t" => Escape::Done('\t'),
+        "u" | "u{" => Escape::Pending,
+        _ => {
+            let Some(hex) = escape.strip_prefix("u{") else {
+                return Escape::Invalid;
+            };
+            let digits = hex.strip_suffix('}').unwrap_or(hex);
+            if digits.len() > 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Escape::Invalid;
+            }
+            if !hex.ends_with('}') {
+                return Escape::Pending;
+            }
+
+            u32::from_str_radix(digits, 16).ok()
+                .and_then(char::from_u32)
+                .map_or(Escape::Invalid, Escape::Done)
+        }
+    }
 }
 
-#[derive(Default)]
 struct CILexerState {
-    tokens: Vec<Token>,
+    tokens: Vec<Spanned<Token>>,
     cur_word: String,
+    word_span: Option<Span>,
 
-    in_string: bool
+    tracker: SpanTracker,
+    in_string: bool,
+    // the chars after a backslash inside a string, and where the backslash was
+    escape: Option<(String, Span)>,
+    comment: Option<Comment>
 }
 
 impl CILexerState {
+    pub fn new(tracker: SpanTracker) -> Self {
+        Self {
+            tokens: Vec::new(),
+            cur_word: String::new(),
+            word_span: None,
+            tracker,
+            in_string: false,
+            escape: None,
+            comment: None
+        }
+    }
+
     pub fn push_token(&mut self, token: Token) {
-        self.tokens.push(token)
+        let start = self.tracker.here();
+        let span = Span { end: start.start + 1, ..start };
+        self.tokens.push(Spanned::new(token, span))
+    }
+
+    // grow the current word's span over `ch`, without adding it to the word
+    pub fn extend_word(&mut self, ch: char) {
+        let here = self.tracker.here();
+        let end = here.start + ch.len_utf8();
+        match &mut self.word_span {
+            Some(span) => span.end = end,
+            None => self.word_span = Some(Span { end, ..here }),
+        }
     }
 
     pub fn push_char(&mut self, ch: char) {
+        self.extend_word(ch);
         self.cur_word.push(ch)
     }
 
@@ -29,25 +130,86 @@ impl CILexerState {
 
     pub fn clear_cur_word(&mut self) {
         self.cur_word.clear();
+        self.word_span = None;
     }
 
     pub fn flush_word(&mut self) {
-        if !self.cur_word.is_empty() {
-            self.push_token(Token::guess_value(&self.cur_word));
+        if let Some(span) = self.word_span.take() {
+            self.tokens.push(Spanned::new(Token::guess_value(&self.cur_word), span));
             self.clear_cur_word();
         }
     }
 
-    pub fn take_tokens(self) -> Vec<Token> {
+    // `'(`, `'{`, `'[` and `'#` quote what follows, a lone `'` isn't an empty ident
+    pub fn flush_word_before_opener(&mut self) {
+        if self.cur_word == "'" {
+            let span = self.word_span.take().unwrap();
+            self.tokens.push(Spanned::new(Token::Quote, span));
+            self.clear_cur_word();
+        } else {
+            self.flush_word();
+        }
+    }
+
+    pub fn flush_string(&mut self) {
+        if let Some(span) = self.word_span.take() {
+            let string = std::mem::take(&mut self.cur_word);
+            self.tokens.push(Spanned::new(Token::Value(Value::String(string)), span));
+        }
+    }
+
+    // `#|` opens a block comment, but only straight after a `#` token
+    pub fn follows_hash(&self) -> bool {
+        let here = self.tracker.here();
+        self.word_span.is_none() && matches!(
+            self.tokens.last(),
+            Some(Spanned { node: Token::Hash, span }) if span.end == here.start
+        )
+    }
+
+    // feed `ch` to the escape sequence being read, if there is one
+    pub fn handle_escape(&mut self, ch: char) -> Option<Result<(), CILexerError>> {
+        let (mut escape, start) = self.escape.take()?;
+        self.extend_word(ch);
+        escape.push(ch);
+
+        match decode_escape(&escape) {
+            Escape::Done(decoded) => self.cur_word.push(decoded),
+            Escape::Pending => self.escape = Some((escape, start)),
+            Escape::Invalid => {
+                let end = self.tracker.here().start + ch.len_utf8();
+                return Some(Err(CILexerError::InvalidEscape(Span { end, ..start })));
+            }
+        }
+        Some(Ok(()))
+    }
+
+    pub fn take_tokens(self) -> Vec<Spanned<Token>> {
         self.tokens
     }
 }
 
-#[derive(Default)]
-pub struct CILexer {}
+pub struct CILexer {
+    source_name: String
+}
 
 impl CILexer {
+    pub fn new(source_name: &str) -> Self {
+        Self { source_name: source_name.to_string() }
+    }
+
     fn handle_char(ch: char, state: &mut CILexerState) -> Result<(), CILexerError> {
+        if let Some(comment) = &mut state.comment {
+            if !comment.step(ch) {
+                state.comment = None;
+            }
+            return Ok(());
+        }
+
+        if let Some(res) = state.handle_escape(ch) {
+            return res;
+        }
+
         match ch {
             ' ' | '\n' | '\t' if !state.in_string => {
                 state.flush_word()
@@ -57,16 +219,20 @@ impl CILexer {
                     // beginning quote
                     state.flush_word();
                     state.toggle_in_string();
-                    state.push_char('"');
+                    state.extend_word('"');
                 } else {
                     // ending quote
-                    state.push_char('"');
+                    state.extend_word('"');
                     state.toggle_in_string();
-                    state.flush_word();
+                    state.flush_string();
                 };
             }
+            '\\' if state.in_string => {
+                state.extend_word('\\');
+                state.escape = Some((String::new(), state.tracker.here()));
+            }
             '(' if !state.in_string => {
-                state.flush_word();
+                state.flush_word_before_opener();
                 state.push_token(Token::LParen);
             }
             ')' if !state.in_string => {
@@ -74,7 +240,7 @@ impl CILexer {
                 state.push_token(Token::RParen);
             }
             '{' if !state.in_string => {
-                state.flush_word();
+                state.flush_word_before_opener();
                 state.push_token(Token::LCurly);
             }
             '}' if !state.in_string => {
@@ -82,7 +248,7 @@ impl CILexer {
                 state.push_token(Token::RCurly);
             }
             '[' if !state.in_string => {
-                state.flush_word();
+                state.flush_word_before_opener();
                 state.push_token(Token::LBracket);
             }
             ']' if !state.in_string => {
@@ -90,9 +256,26 @@ impl CILexer {
                 state.push_token(Token::RBracket);
             }
             '#' if !state.in_string => {
-                state.flush_word();
+                state.flush_word_before_opener();
                 state.push_token(Token::Hash);
             }
+            '`' if !state.in_string => {
+                state.flush_word();
+                state.push_token(Token::Quasiquote);
+            }
+            ',' if !state.in_string => {
+                state.flush_word();
+                state.push_token(Token::Unquote);
+            }
+            ';' if !state.in_string => {
+                state.flush_word();
+                state.comment = Some(Comment::Line);
+            }
+            '|' if !state.in_string && state.follows_hash() => {
+                let hash = state.tokens.pop().unwrap();
+                let start = Span { end: hash.span.end + 1, ..hash.span };
+                state.comment = Some(Comment::Block { depth: 1, prev: ' ', start });
+            }
             a => state.push_char(a)
         }
         
@@ -100,24 +283,38 @@ impl CILexer {
     }
 }
 
+impl Default for CILexer {
+    fn default() -> Self {
+        Self::new("<input>")
+    }
+}
+
 impl Parser for CILexer {
     type Input = String;
-    type Output = Vec<Token>;
+    type Output = Vec<Spanned<Token>>;
 
-    fn parse(&self, tokens: String) -> Result<Vec<Token>, CIParserError> {
-        let mut state = CILexerState::default();
+    fn parse(&self, tokens: String) -> Result<Vec<Spanned<Token>>, CIParserError> {
+        let source = Source::new(&self.source_name, &tokens);
+        let mut state = CILexerState::new(SpanTracker::new(source));
 
         for i in tokens.chars() {
             Self::handle_char(i, &mut state)?;
+            state.tracker.advance(i);
+        }
+
+        if let Some(Comment::Block { start, .. }) = state.comment {
+            return Err(CILexerError::UnclosedComment(start))?;
         }
 
         if state.in_string {
-            return Err(CILexerError::UnmatchedQuotes)?;
+            let span = state.word_span.take().unwrap_or_else(|| state.tracker.here());
+            return Err(CILexerError::UnmatchedQuotes(Span { end: span.start + 1, ..span }))?;
         } else {
             state.flush_word();
         }
 
-        state.push_token(Token::EOF);
+        let eof = state.tracker.here();
+        state.tokens.push(Spanned::new(Token::EOF, eof));
 
         Ok(state.take_tokens())
     }
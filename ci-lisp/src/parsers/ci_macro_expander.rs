@@ -0,0 +1,263 @@
+use std::{collections::HashMap, fs, path::PathBuf, rc::Rc};
+
+use crate::{ast::{AstNode, Function, Value}, env::Environment, parser_types::{CIParserError, Parser, SeqParsers}, parsers::{CIEvalError, CIFileEvaluator, CIFullFileParser, CIIntermediateTokenizer, CILexer, CINewFileParser}};
+
+// How many macro calls can expand inside one another before we assume a macro never stops expanding
+pub const MAX_MACRO_DEPTH: usize = 1_000;
+
+// the macros a file defines, in order
+type Macros = Vec<(String, AstNode)>;
+
+// The files `expand_forms` is reading macros from
+#[derive(Default)]
+struct Includes {
+    // canonical paths and the names they were included by, each one included by the one before it
+    chain: Vec<(PathBuf, String)>,
+    // what each file defined, so including a file again doesn't read it again
+    macros: HashMap<PathBuf, Macros>,
+}
+
+// Rewrites calls to macros before anything is evaluated. A macro is a function that gets
+// the whole call quoted, `'((-> x) f)`, and returns the code to run in its place.
+pub struct CIMacroExpander {
+    env: Environment
+}
+
+impl CIMacroExpander {
+    pub fn new(env: Environment) -> Self {
+        Self { env }
+    }
+
+    // expand every macro call in `node`, including the ones the expansions produce
+    pub fn expand(node: &Rc<AstNode>, env: &Environment) -> Result<Rc<AstNode>, CIEvalError> {
+        Self::expand_at(node, env, &mut Vec::new(), 0)
+    }
+
+    // `bound` are the parameters in scope, a call to one of them isn't a macro call even if a macro has its name
+    fn expand_at(node: &Rc<AstNode>, env: &Environment, bound: &mut Vec<String>, depth: usize) -> Result<Rc<AstNode>, CIEvalError> {
+        match node.as_ref() {
+            AstNode::Spanned { span, node } => Ok(Rc::new(AstNode::Spanned {
+                span: span.clone(),
+                node: Self::expand_at(node, env, bound, depth).map_err(|e| e.at(span))?,
+            })),
+
+            AstNode::Par { car, cdr } => match Self::macro_call(node, env).filter(|(name, _)| !bound.contains(name)) {
+                Some((name, expander)) => {
+                    if depth >= MAX_MACRO_DEPTH {
+                        return Err(CIEvalError::MacroDepth(name, MAX_MACRO_DEPTH));
+                    }
+                    let expanded = Self::expand_once(&expander, node, env)?;
+                    Self::expand_at(&expanded, env, bound, depth + 1)
+                }
+                None => Ok(Rc::new(AstNode::Par {
+                    car: Self::expand_at(car, env, bound, depth)?,
+                    cdr: Self::expand_at(cdr, env, bound, depth)?,
+                })),
+            },
+
+            AstNode::Lambda { varname, body } => {
+                bound.push(varname.clone());
+                let body = Self::expand_at(body, env, bound, depth);
+                bound.pop();
+
+                Ok(Rc::new(AstNode::Lambda { varname: varname.clone(), body: body? }))
+            }
+
+            // quoted code is data, it only gets expanded if it's evaluated later on
+            _ => Ok(node.clone()),
+        }
+    }
+
+    // hand the quoted call to the expander and take back the code it returns
+    pub fn expand_once(expander: &AstNode, call: &Rc<AstNode>, env: &Environment) -> Result<Rc<AstNode>, CIEvalError> {
+        let application = AstNode::Par {
+            car: Rc::new(expander.clone()),
+            cdr: Rc::new(AstNode::data(call)),
+        };
+
+        let evaluator = CIFileEvaluator::new(env.clone());
+        let (expanded, _) = evaluator.eval_node(&application, env.clone())?;
+        Ok(expanded.code())
+    }
+
+    // the name and expander of the macro `call` applies, if its head names one.
+    // `(-> x f g)` is `(((-> x) f) g)`, so the head is at the bottom of the `car`s
+    pub fn macro_call(call: &AstNode, env: &Environment) -> Option<(String, AstNode)> {
+        let mut head = call.unspanned();
+        while let AstNode::Par { car, cdr: _ } = head {
+            head = car.unspanned();
+        }
+
+        let AstNode::Value(Value::Symbol(name)) = head else {
+            return None;
+        };
+        match &env.get(name)? {
+            AstNode::Function(Function::Macro(expander)) => Some((name.clone(), expander.as_ref().clone())),
+            _ => None,
+        }
+    }
+
+    // expand a file's forms in order, along with the macros they define
+    fn expand_forms(ast: Vec<AstNode>, env: Environment, includes: &mut Includes) -> Result<(Vec<AstNode>, Macros), CIParserError> {
+        let mut env = env;
+        let mut nodes = Vec::new();
+        let mut defined = Vec::new();
+
+        for i in ast.into_iter() {
+            let node = Self::expand(&Rc::new(i), &env)?;
+            let at_node = |e: CIEvalError| match node.span() {
+                Some(span) => e.at(span),
+                None => e,
+            };
+
+            // the forms after a `defmacro` or an `include` can use its macros straight away,
+            // before the file is evaluated. They're evaluated later as well, to keep the macros around
+            let macros = if let Some((name, expander)) = Self::defmacro_form(&node) {
+                let evaluator = CIFileEvaluator::new(env.clone());
+                let (expander, _) = evaluator.eval_node(&expander, env.clone()).map_err(at_node)?;
+                vec![(name, AstNode::Function(Function::Macro(Rc::new(expander))))]
+            } else if let Some(filename) = Self::include_form(&node) {
+                Self::included_macros(&filename, &env, includes).map_err(at_node)?
+            } else {
+                Vec::new()
+            };
+
+            env = macros.iter().fold(env, |env, (name, expander)| env.insert(name, expander.clone()));
+            defined.extend(macros);
+            nodes.push(node.as_ref().clone());
+        }
+
+        Ok((nodes, defined))
+    }
+
+    // The macros `filename` defines. Problems with the file are left for `include` to report
+    // when it's evaluated, except for the file including itself, which would never stop
+    fn included_macros(filename: &str, env: &Environment, includes: &mut Includes) -> Result<Macros, CIEvalError> {
+        let Ok(path) = fs::canonicalize(filename) else {
+            return Ok(Vec::new());
+        };
+        if let Some(at) = includes.chain.iter().position(|(included, _)| *included == path) {
+            let cycle = includes.chain[at..].iter().map(|(_, name)| name.clone()).chain([filename.to_string()]);
+            return Err(CIEvalError::IncludeCycle(cycle.collect()));
+        }
+        if let Some(macros) = includes.macros.get(&path) {
+            return Ok(macros.clone());
+        }
+
+        let Ok(source) = fs::read_to_string(&path) else {
+            return Ok(Vec::new());
+        };
+        let parser = CIFullFileParser::new(
+            SeqParsers::new(CILexer::new(filename), CIIntermediateTokenizer::default()),
+            CINewFileParser::default()
+        );
+        let Ok(nodes) = parser.parse(source) else {
+            return Ok(Vec::new());
+        };
+
+        includes.chain.push((path.clone(), filename.to_string()));
+        let expanded = Self::expand_forms(nodes, env.clone(), includes);
+        includes.chain.pop();
+
+        let macros = match expanded {
+            Ok((_, macros)) => macros,
+            Err(CIParserError::EvalError(e)) if matches!(e.root(), CIEvalError::IncludeCycle(_)) => return Err(e),
+            Err(_) => Vec::new(),
+        };
+        includes.macros.insert(path, macros.clone());
+        Ok(macros)
+    }
+
+    // `(include "file")`
+    fn include_form(node: &AstNode) -> Option<String> {
+        let AstNode::Par { car, cdr } = node.unspanned() else {
+            return None;
+        };
+
+        match (car.unspanned(), cdr.unspanned()) {
+            (AstNode::Value(Value::Symbol(head)), AstNode::Value(Value::String(filename))) if head == "include" => {
+                Some(filename.clone())
+            }
+            _ => None,
+        }
+    }
+
+    // `((defmacro expander) 'name)`, the same shape as `def`
+    fn defmacro_form(node: &AstNode) -> Option<(String, Rc<AstNode>)> {
+        let AstNode::Par { car, cdr: name } = node.unspanned() else {
+            return None;
+        };
+        let AstNode::Par { car: head, cdr: expander } = car.unspanned() else {
+            return None;
+        };
+
+        match (head.unspanned(), name.unspanned()) {
+            (AstNode::Value(Value::Symbol(head)), AstNode::Value(Value::Ident(name))) if head == "defmacro" => {
+                Some((name.clone(), expander.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Parser for CIMacroExpander {
+    type Input = Vec<AstNode>;
+    type Output = Vec<AstNode>;
+
+    fn parse(&self, ast: Vec<AstNode>) -> Result<Vec<AstNode>, CIParserError> {
+        let (nodes, _) = Self::expand_forms(ast, self.env.clone(), &mut Includes::default())?;
+        Ok(nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{parser_types::CIParserError, parsers::CIEvalError, test_util::{eval_in, full_environment}};
+
+    // write `source` to a file of its own under the temp dir, giving back its path
+    fn temp_file(test: &str, name: &str, source: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("ci-lisp-{test}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, source).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn include_cycle(path: &str) -> Vec<String> {
+        match eval_in(full_environment(), &[], &format!("(include {path:?})")) {
+            Err(CIParserError::EvalError(e)) => match e.root() {
+                CIEvalError::IncludeCycle(cycle) => cycle.clone(),
+                other => panic!("{other}"),
+            },
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_file_including_itself_is_an_error() {
+        let path = temp_file("self", "self.ci", "");
+        fs::write(&path, format!("(include {path:?})")).unwrap();
+
+        assert_eq!(include_cycle(&path), [path.clone(), path]);
+    }
+
+    #[test]
+    fn files_including_each_other_is_an_error() {
+        let a = temp_file("each_other", "a.ci", "");
+        let b = temp_file("each_other", "b.ci", &format!("(include {a:?})"));
+        fs::write(&a, format!("(include {b:?})")).unwrap();
+
+        assert_eq!(include_cycle(&a), [a.clone(), b, a]);
+    }
+
+    #[test]
+    fn parameters_shadow_macros() {
+        let answer = temp_file("shadow", "answer.ci", "((defmacro (fn 'call 42)) 'answer)");
+        let eval = |source| eval_in(full_environment(), &[&answer], source).unwrap().to_string();
+
+        assert_eq!(eval("(answer 1)"), "42");
+        assert_eq!(eval("((fn 'answer (answer 1)) (fn 'x x))"), "1");
+    }
+}
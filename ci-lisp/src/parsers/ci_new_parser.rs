@@ -1,94 +1,214 @@
+use std::rc::Rc;
+
+use im::HashMap;
+
 use crate::{
     ast::{AstNode, IntermediateToken, Value},
     parser_types::{CIParserError, Parser},
+    span::{Span, Spanned},
 };
 
-pub struct TokenStream<I: Iterator<Item = IntermediateToken>> {
+pub struct TokenStream<I: Iterator<Item = Spanned<IntermediateToken>>> {
     iter: I,
 }
 
-impl<I: Iterator<Item = IntermediateToken>> TokenStream<I> {
+impl<I: Iterator<Item = Spanned<IntermediateToken>>> TokenStream<I> {
     pub fn new(iter: I) -> Self {
         Self { iter }
     }
 
-    pub fn next(&mut self) -> Option<IntermediateToken> {
+    pub fn next(&mut self) -> Option<Spanned<IntermediateToken>> {
         self.iter.next()
     }
 }
 
+fn unexpected_node(node: &AstNode) -> CIParserError {
+    let span = node.span().cloned().expect("parsed nodes are always spanned");
+    CIParserError::UnexpectedToken(Box::new(Spanned::new(IntermediateToken::AstNode(node.clone()), span)))
+}
+
+// `['a 'b 'c]` in `(fn ['a 'b 'c] body)`, along with where it is
+fn parse_params(
+    stream: &mut TokenStream<impl Iterator<Item = Spanned<IntermediateToken>>>,
+    level: i32,
+    open: Span,
+) -> Result<(Vec<String>, Span), CIParserError> {
+    let mut params = Vec::new();
+    let mut span = open.clone();
+
+    while let Some(tok) = stream.next() {
+        match tok.node {
+            IntermediateToken::RBracket(l) if l == level => {
+                span = open.to(&tok.span);
+                if params.is_empty() {
+                    return Err(CIParserError::NoParameters(span));
+                }
+                break
+            },
+            IntermediateToken::Value(Value::Ident(name)) => params.push(name),
+            _ => return Err(CIParserError::UnexpectedToken(Box::new(tok))),
+        }
+    }
+
+    Ok((params, span))
+}
+
+fn is_fn(node: &AstNode) -> bool {
+    matches!(node.unspanned(), AstNode::Value(Value::Symbol(s)) if s == "fn")
+}
+
 fn parse_paren(
-    stream: &mut TokenStream<impl Iterator<Item = IntermediateToken>>,
+    stream: &mut TokenStream<impl Iterator<Item = Spanned<IntermediateToken>>>,
     level: i32,
+    open: Span,
 ) -> Result<AstNode, CIParserError> {
     let mut items = Vec::new();
+    let mut params = None;
+    let mut span = open.clone();
 
     while let Some(tok) = stream.next() {
-        match tok {
-            IntermediateToken::RParen(l) if l == level => break,
-            other => items.push(parse_token(other, stream)?),
+        match tok.node {
+            IntermediateToken::RParen(l) if l == level => { span = open.to(&tok.span); break },
+            IntermediateToken::LBracket(l) if items.len() == 1 && is_fn(&items[0]) => {
+                params = Some(parse_params(stream, l, tok.span)?);
+            }
+            _ => items.push(parse_token(tok, stream)?),
         }
     }
 
-    match items.as_slice() {
-        [] => Ok(AstNode::Value(Value::Nil)),
-        [a] => Ok(a.clone()),
-        [AstNode::Value(Value::Symbol(s)), arg, body] if s == "fn" => {
+    // (fn ['a 'b] body) => (fn 'a (fn 'b body))
+    if let Some((params, params_span)) = params {
+        if items.len() == 1 {
+            return Err(CIParserError::MissingBody(params_span));
+        }
+        if items.len() != 2 {
+            return Err(CIParserError::NodeFull(items, span));
+        }
+
+        let mut node = items.pop().unwrap();
+        for (i, varname) in params.into_iter().enumerate().rev() {
+            node = AstNode::Lambda { varname, body: Rc::new(node) };
+            if i > 0 {
+                node = node.spanned(span.clone());
+            }
+        }
+
+        return Ok(node.spanned(span));
+    }
+
+    let bare: Vec<&AstNode> = items.iter().map(AstNode::unspanned).collect();
+
+    let node = match bare.as_slice() {
+        [] => AstNode::Value(Value::Nil),
+        // (cond (a x) (b y)) => (((builtin__if a) x) (((builtin__if b) y) nil))
+        [AstNode::Value(Value::Symbol(s)), clauses @ ..] if s == "cond" => {
+            let mut node = AstNode::Value(Value::Nil);
+            for (clause, bare_clause) in items[1..].iter().zip(clauses).rev() {
+                let AstNode::Par { car: test, cdr: expr } = bare_clause else {
+                    return Err(unexpected_node(clause));
+                };
+
+                node = AstNode::Par {
+                    car: Rc::new(AstNode::Par {
+                        car: Rc::new(AstNode::Par {
+                            car: Rc::new(AstNode::Value(Value::Symbol("builtin__if".to_string()))),
+                            cdr: test.clone(),
+                        }),
+                        cdr: expr.clone(),
+                    }),
+                    cdr: Rc::new(node),
+                };
+            }
+            node
+        }
+        [AstNode::Value(Value::Symbol(s)), _] if s == "quote" => AstNode::Quote(Rc::new(items[1].clone())),
+        [AstNode::Value(Value::Symbol(s)), _] if s == "quasiquote" => expand_quasiquote(&items[1]),
+        [_] => return Ok(items[0].clone()),
+        [AstNode::Value(Value::Symbol(s)), AstNode::Value(Value::Ident(_))] if s == "fn" => {
+            let span = items[1].span().cloned().expect("parsed nodes are always spanned");
+            return Err(CIParserError::MissingBody(span));
+        }
+        [AstNode::Value(Value::Symbol(s)), arg, _] if s == "fn" => {
             let arg_ident = match arg {
                 AstNode::Value(Value::Ident(name)) => name.clone(),
-                _ => return Err(CIParserError::UnexpectedToken(Box::new(IntermediateToken::AstNode(arg.clone())))),
+                _ => return Err(unexpected_node(&items[1])),
             };
 
-            Ok(AstNode::Lambda {
+            AstNode::Lambda {
                 varname: arg_ident,
-                body: Box::new(body.clone()),
-            })
+                body: Rc::new(items[2].clone()),
+            }
         }
-        [a, b] => Ok(AstNode::Par {
-            car: Box::new(a.clone()),
-            cdr: Box::new(b.clone()),
-        }),
-        _ => Err(CIParserError::NodeFull(items)),
-    }
+        [AstNode::Value(Value::Symbol(s)), _, _, _, ..] if s == "fn" => {
+            return Err(CIParserError::NodeFull(items, span));
+        }
+        // (f a b c) => (((f a) b) c)
+        _ => {
+            let mut args = items.into_iter();
+            let mut node = args.next().unwrap();
+
+            while let Some(arg) = args.next() {
+                let partial_span = match args.len() {
+                    0 => span.clone(),
+                    _ => open.to(arg.span().unwrap_or(&span)),
+                };
+
+                node = AstNode::Par {
+                    car: Rc::new(node),
+                    cdr: Rc::new(arg),
+                }.spanned(partial_span);
+            }
+
+            return Ok(node);
+        }
+    };
+
+    Ok(node.spanned(span))
 }
 
 fn parse_infix(
-    stream: &mut TokenStream<impl Iterator<Item = IntermediateToken>>,
+    stream: &mut TokenStream<impl Iterator<Item = Spanned<IntermediateToken>>>,
     level: i32,
+    open: Span,
 ) -> Result<AstNode, CIParserError> {
     let mut nodes = Vec::new();
+    let mut span = open.clone();
 
     while let Some(tok) = stream.next() {
-        match tok {
-            IntermediateToken::RCurly(l) if l == level => break,
-            other => nodes.push(parse_token(other, stream)?),
+        match tok.node {
+            IntermediateToken::RCurly(l) if l == level => { span = open.to(&tok.span); break },
+            _ => nodes.push(parse_token(tok, stream)?),
         }
     }
 
-    match nodes.as_slice() {
-        [] => Ok(AstNode::Value(Value::Nil)),
-        [a] => Ok(a.clone()),
-        [a, b, c] => Ok(AstNode::Par {
-            car: Box::new(AstNode::Par {
-                car: Box::new(b.clone()),
-                cdr: Box::new(c.clone()),
-            }),
-            cdr: Box::new(a.clone()),
-        }),
-        _ => Err(CIParserError::NodeFull(nodes)),
-    }
+    let node = match nodes.as_slice() {
+        [] => AstNode::Value(Value::Nil),
+        [a] => return Ok(a.clone()),
+        [a, b, c] => AstNode::Par {
+            car: Rc::new(AstNode::Par {
+                car: Rc::new(b.clone()),
+                cdr: Rc::new(c.clone()),
+            }.spanned(b.span().unwrap().to(c.span().unwrap()))),
+            cdr: Rc::new(a.clone()),
+        },
+        _ => return Err(CIParserError::NodeFull(nodes, span)),
+    };
+
+    Ok(node.spanned(span))
 }
 
 fn parse_list(
-    stream: &mut TokenStream<impl Iterator<Item = IntermediateToken>>,
+    stream: &mut TokenStream<impl Iterator<Item = Spanned<IntermediateToken>>>,
     level: i32,
+    open: Span,
 ) -> Result<AstNode, CIParserError> {
     let mut items = Vec::new();
+    let mut span = open.clone();
 
     while let Some(tok) = stream.next() {
-        match tok {
-            IntermediateToken::RBracket(l) if l == level => break,
-            other => items.push(parse_token(other, stream)?),
+        match tok.node {
+            IntermediateToken::RBracket(l) if l == level => { span = open.to(&tok.span); break },
+            _ => items.push(parse_token(tok, stream)?),
         }
     }
 
@@ -96,60 +216,158 @@ fn parse_list(
 
     for item in items.into_iter().rev() {
         result = AstNode::Par {
-            car: Box::new(AstNode::Par {
-                car: Box::new(AstNode::Value(Value::Symbol("cons".to_string()))),
-                cdr: Box::new(result),
+            car: Rc::new(AstNode::Par {
+                car: Rc::new(AstNode::Value(Value::Symbol("builtin__cons".to_string()))),
+                cdr: Rc::new(result),
             }),
-            cdr: Box::new(item),
+            cdr: Rc::new(item),
         };
     }
 
-    Ok(result)
+    Ok(result.spanned(span))
+}
+
+// #{k1 v1 k2 v2} => (((builtin__assoc k2) v2) (((builtin__assoc k1) v1) #{}))
+fn parse_map(
+    stream: &mut TokenStream<impl Iterator<Item = Spanned<IntermediateToken>>>,
+    level: i32,
+    open: Span,
+) -> Result<AstNode, CIParserError> {
+    let mut items = Vec::new();
+    let mut span = open.clone();
+
+    while let Some(tok) = stream.next() {
+        match tok.node {
+            IntermediateToken::RCurly(l) if l == level => { span = open.to(&tok.span); break },
+            _ => items.push(parse_token(tok, stream)?),
+        }
+    }
+
+    if items.len() % 2 != 0 {
+        return Err(CIParserError::UnpairedMapKey(span));
+    }
+
+    let mut result = AstNode::Map(HashMap::new());
+    let mut pairs = Vec::new();
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        pairs.push((key, value));
+    }
+
+    // later keys win, so they're assoc'd last
+    for (key, value) in pairs.into_iter() {
+        result = AstNode::Par {
+            car: Rc::new(AstNode::Par {
+                car: Rc::new(AstNode::Par {
+                    car: Rc::new(AstNode::Value(Value::Symbol("builtin__assoc".to_string()))),
+                    cdr: Rc::new(key),
+                }),
+                cdr: Rc::new(value),
+            }),
+            cdr: Rc::new(result),
+        };
+    }
+
+    Ok(result.spanned(span))
+}
+
+// Build the code that constructs a quasiquoted template at runtime:
+// `(f ,x) => ((builtin__make_par 'f) x)
+fn expand_quasiquote(template: &AstNode) -> AstNode {
+    let constructor = |name: &str, a: AstNode, b: AstNode| AstNode::Par {
+        car: Rc::new(AstNode::Par {
+            car: Rc::new(AstNode::Value(Value::Symbol(name.to_string()))),
+            cdr: Rc::new(a),
+        }),
+        cdr: Rc::new(b),
+    };
+
+    let node = match template.unspanned() {
+        AstNode::Par { car, cdr } => match car.unspanned() {
+            AstNode::Value(Value::Symbol(s)) if s == "unquote" => return cdr.as_ref().clone(),
+            _ => constructor("builtin__make_par", expand_quasiquote(car), expand_quasiquote(cdr)),
+        },
+        AstNode::Lambda { varname, body } => {
+            constructor("builtin__make_lambda", AstNode::Value(Value::Ident(varname.clone())), expand_quasiquote(body))
+        }
+        _ => AstNode::Quote(Rc::new(template.clone())),
+    };
+
+    match template.span() {
+        Some(span) => node.spanned(span.clone()),
+        None => node,
+    }
 }
 
 fn parse_token(
-    token: IntermediateToken,
-    stream: &mut TokenStream<impl Iterator<Item = IntermediateToken>>,
+    token: Spanned<IntermediateToken>,
+    stream: &mut TokenStream<impl Iterator<Item = Spanned<IntermediateToken>>>,
 ) -> Result<AstNode, CIParserError> {
+    let Spanned { node: token, span } = token;
+
     match token {
-        IntermediateToken::Value(v) => Ok(AstNode::Value(v)),
+        IntermediateToken::Value(v) => Ok(AstNode::Value(v).spanned(span)),
 
         // Add support for Church numeral prefix: #7
         IntermediateToken::Hash => {
             match stream.next() {
-                Some(IntermediateToken::Value(Value::Int(n))) if n >= 0 => {
+                Some(Spanned { node: IntermediateToken::Value(Value::Int(n)), span: num_span }) if !n.is_negative() => {
+                    let n = n.to_usize().map_err(|e| e.at(&num_span))?;
+
                     // Build Church numeral: succ^n zero
                     let mut node = AstNode::Value(Value::Symbol("zero".to_string()));
                     for _ in 0..n {
                         node = AstNode::Par {
-                            car: Box::new(AstNode::Value(Value::Symbol("succ".to_string()))),
-                            cdr: Box::new(node),
+                            car: Rc::new(AstNode::Value(Value::Symbol("succ".to_string()))),
+                            cdr: Rc::new(node),
                         };
                     }
-                    Ok(node)
+                    Ok(node.spanned(span.to(&num_span)))
+                }
+                Some(Spanned { node: IntermediateToken::LCurly(level), span: open }) => {
+                    parse_map(stream, level, span.to(&open))
                 }
                 Some(other) => Err(CIParserError::UnexpectedToken(Box::new(other))),
-                None => Err(CIParserError::UnexpectedToken(Box::new(IntermediateToken::EOF))),
+                None => Err(CIParserError::UnexpectedToken(Box::new(Spanned::new(IntermediateToken::EOF, span)))),
             }
         }
         
+        // 'x => (quote x), `x => (quasiquote x), ,x => (unquote x)
+        IntermediateToken::Quote | IntermediateToken::Quasiquote | IntermediateToken::Unquote => {
+            let quoted = match stream.next() {
+                Some(tok) => parse_token(tok, stream)?,
+                None => return Err(CIParserError::UnexpectedToken(Box::new(Spanned::new(IntermediateToken::EOF, span)))),
+            };
+            let span = span.to(quoted.span().unwrap_or(&span));
+
+            Ok(match token {
+                IntermediateToken::Quote => AstNode::Quote(Rc::new(quoted)).spanned(span),
+                IntermediateToken::Quasiquote => expand_quasiquote(&quoted).spanned(span),
+                _ => AstNode::Par {
+                    car: Rc::new(AstNode::Value(Value::Symbol("unquote".to_string()))),
+                    cdr: Rc::new(quoted),
+                }.spanned(span),
+            })
+        }
+
         IntermediateToken::AstNode(n) => Ok(n),
-        IntermediateToken::LParen(level) => parse_paren(stream, level),
-        IntermediateToken::LCurly(level) => parse_infix(stream, level),
-        IntermediateToken::LBracket(level) => parse_list(stream, level),
-        other => Err(CIParserError::UnexpectedToken(Box::new(other))),
+        IntermediateToken::LParen(level) => parse_paren(stream, level, span),
+        IntermediateToken::LCurly(level) => parse_infix(stream, level, span),
+        IntermediateToken::LBracket(level) => parse_list(stream, level, span),
+        other => Err(CIParserError::UnexpectedToken(Box::new(Spanned::new(other, span)))),
     }
 }
 
 fn parse_virtual_infix(
-    stream: &mut TokenStream<impl Iterator<Item = IntermediateToken>>,
+    stream: &mut TokenStream<impl Iterator<Item = Spanned<IntermediateToken>>>,
 ) -> Result<AstNode, CIParserError> {
     let mut nodes = Vec::new();
+    let mut eof_span = None;
 
     while let Some(tok) = stream.next() {
-        match tok {
-            IntermediateToken::EOF => break,
-            other => nodes.push(parse_token(other, stream)?),
+        match tok.node {
+            IntermediateToken::EOF => { eof_span = Some(tok.span); break },
+            _ => nodes.push(parse_token(tok, stream)?),
         }
     }
 
@@ -157,19 +375,22 @@ fn parse_virtual_infix(
         [] => Ok(AstNode::Value(Value::Nil)),
         [a] => Ok(a.clone()),
         [a, b, c] => Ok(AstNode::Par {
-            car: Box::new(AstNode::Par {
-                car: Box::new(b.clone()),
-                cdr: Box::new(c.clone()),
-            }),
-            cdr: Box::new(a.clone()),
-        }),
-        _ => Err(CIParserError::NodeFull(nodes)),
+            car: Rc::new(AstNode::Par {
+                car: Rc::new(b.clone()),
+                cdr: Rc::new(c.clone()),
+            }.spanned(b.span().unwrap().to(c.span().unwrap()))),
+            cdr: Rc::new(a.clone()),
+        }.spanned(a.span().unwrap().to(c.span().unwrap()))),
+        [a, ..] => {
+            let span = a.span().unwrap().to(eof_span.as_ref().unwrap_or(a.span().unwrap()));
+            Err(CIParserError::NodeFull(nodes.clone(), span))
+        },
     }
 }
 
-fn ensure_stream_ended<I: Iterator<Item = IntermediateToken>>(stream: &mut TokenStream<I>) -> Result<(), CIParserError> {
+fn ensure_stream_ended<I: Iterator<Item = Spanned<IntermediateToken>>>(stream: &mut TokenStream<I>) -> Result<(), CIParserError> {
     match stream.next() {
-        None | Some(IntermediateToken::EOF) => Ok(()),
+        None | Some(Spanned { node: IntermediateToken::EOF, span: _ }) => Ok(()),
         Some(extra) => Err(CIParserError::UnexpectedToken(Box::new(extra))),
     }
 }
@@ -186,10 +407,10 @@ impl CINewReplParser {
 }
 
 impl Parser for CINewReplParser {
-    type Input = Vec<IntermediateToken>;
+    type Input = Vec<Spanned<IntermediateToken>>;
     type Output = AstNode;
 
-    fn parse(&self, tokens: Vec<IntermediateToken>) -> Result<AstNode, CIParserError> {
+    fn parse(&self, tokens: Vec<Spanned<IntermediateToken>>) -> Result<AstNode, CIParserError> {
         let mut stream = TokenStream::new(tokens.into_iter());
 
         if self.infix_repl {
@@ -200,23 +421,23 @@ impl Parser for CINewReplParser {
         }
 
         match stream.next() {
-            Some(IntermediateToken::LParen(level)) => {
-                let result = parse_paren(&mut stream, level)?;
+            Some(Spanned { node: IntermediateToken::LParen(level), span }) => {
+                let result = parse_paren(&mut stream, level, span)?;
                 ensure_stream_ended(&mut stream)?;
                 Ok(result)
             }
-            Some(IntermediateToken::LBracket(level)) => {
-                let result = parse_list(&mut stream, level)?;
+            Some(Spanned { node: IntermediateToken::LBracket(level), span }) => {
+                let result = parse_list(&mut stream, level, span)?;
                 ensure_stream_ended(&mut stream)?;
                 Ok(result)
             }
-            Some(IntermediateToken::LCurly(level)) => {
-                let result = parse_infix(&mut stream, level)?;
+            Some(Spanned { node: IntermediateToken::LCurly(level), span }) => {
+                let result = parse_infix(&mut stream, level, span)?;
                 ensure_stream_ended(&mut stream)?;
                 Ok(result)
             }
             Some(tok) => Err(CIParserError::UnexpectedToken(Box::new(tok))),
-            None => Err(CIParserError::UnexpectedToken(Box::new(IntermediateToken::EOF))),
+            None => Ok(AstNode::Value(Value::Nil)),
         }
     }
 }
@@ -225,30 +446,30 @@ impl Parser for CINewReplParser {
 pub struct CINewFileParser {}
 
 impl Parser for CINewFileParser {
-    type Input = Vec<IntermediateToken>;
+    type Input = Vec<Spanned<IntermediateToken>>;
     type Output = Vec<AstNode>;
 
-    fn parse(&self, tokens: Vec<IntermediateToken>) -> Result<Vec<AstNode>, CIParserError> {
+    fn parse(&self, tokens: Vec<Spanned<IntermediateToken>>) -> Result<Vec<AstNode>, CIParserError> {
         let mut stream = TokenStream::new(tokens.into_iter());
         let mut forms = Vec::new();
 
-        while let Some(tok) = stream.next() {
+        while let Some(Spanned { node: tok, span }) = stream.next() {
             match tok {
                 IntermediateToken::LParen(level) => {
-                    let node = parse_paren(&mut stream, level)?;
+                    let node = parse_paren(&mut stream, level, span)?;
                     forms.push(node);
                 }
                 IntermediateToken::LBracket(level) => {
-                    let node = parse_list(&mut stream, level)?;
+                    let node = parse_list(&mut stream, level, span)?;
                     forms.push(node);
                 }
                 IntermediateToken::LCurly(level) => {
-                    let node = parse_infix(&mut stream, level)?;
+                    let node = parse_infix(&mut stream, level, span)?;
                     forms.push(node);
                 }
                 IntermediateToken::EOF => break,
                 unexpected => {
-                    return Err(CIParserError::UnexpectedToken(Box::new(unexpected)));
+                    return Err(CIParserError::UnexpectedToken(Box::new(Spanned::new(unexpected, span))));
                 }
             }
         }
@@ -1,4 +1,6 @@
-use crate::{ast::{AstNode, Value}, env::Environment, parser_types::{CIParserError, Parser}, parsers::CIFileEvaluator};
+use std::rc::Rc;
+
+use crate::{ast::{AstNode, Value}, env::Environment, parser_types::{CIParserError, HasEnv, Parser}, parsers::{CIFileEvaluator, CIMacroExpander}};
 
 pub struct CIReplEvaluator {
     preload: Vec<String>,
@@ -14,6 +16,16 @@ impl CIReplEvaluator {
     }
 }
 
+impl HasEnv for CIReplEvaluator {
+    fn env(&self) -> Environment {
+        self.file_evaluator.env()
+    }
+
+    fn set_env(&self, env: Environment) {
+        self.file_evaluator.set_env(env)
+    }
+}
+
 impl Parser for CIReplEvaluator {
     type Input = AstNode;
     type Output = AstNode;
@@ -21,11 +33,15 @@ impl Parser for CIReplEvaluator {
     fn parse(&self, ast: AstNode) -> Result<AstNode, CIParserError> {
         for i in self.preload.iter() {
             self.file_evaluator.parse(vec![AstNode::Par {
-                car: Box::new(AstNode::Value(Value::Symbol("include".to_string()))),
-                cdr: Box::new(AstNode::Value(Value::String(i.to_string())))
+                car: Rc::new(AstNode::Value(Value::Symbol("include".to_string()))),
+                cdr: Rc::new(AstNode::Value(Value::String(i.to_string())))
             }])?;
         }
 
-        Ok(self.file_evaluator.parse(vec![ast])?[0].clone())
+        // expand with the macros the earlier lines and the preloaded libraries defined
+        let expander = CIMacroExpander::new(self.file_evaluator.env());
+        let nodes = expander.parse(vec![ast])?;
+
+        Ok(self.file_evaluator.parse(nodes)?[0].clone())
     }
 }
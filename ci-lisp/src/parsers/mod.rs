@@ -8,11 +8,14 @@ mod ci_intermediate_tokenizer;
 pub use ci_intermediate_tokenizer::CIIntermediateTokenizer;
 
 mod ci_evaluator;
-pub use ci_evaluator::{CIEvalError, CIFileEvaluator};
+pub use ci_evaluator::{CallSite, CIEvalError, CIFileEvaluator, Strategy};
 
 mod ci_repl_evaluator;
 pub use ci_repl_evaluator::CIReplEvaluator;
 
+mod ci_macro_expander;
+pub use ci_macro_expander::CIMacroExpander;
+
 mod ci_new_parser;
 pub use ci_new_parser::{CINewReplParser, CINewFileParser};
 
@@ -20,4 +23,4 @@ pub use ci_new_parser::{CINewReplParser, CINewFileParser};
 
 use crate::parser_types::SeqParsers;
 pub type CIFullFileParser = SeqParsers<SeqParsers<CILexer, CIIntermediateTokenizer>, CINewFileParser>;
-pub type CIFullFileEvaluator = SeqParsers<CIFullFileParser, CIFileEvaluator>;
+pub type CIFullFileEvaluator = SeqParsers<SeqParsers<CIFullFileParser, CIMacroExpander>, CIFileEvaluator>;
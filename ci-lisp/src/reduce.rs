@@ -0,0 +1,222 @@
+use std::{collections::HashSet, rc::Rc};
+
+use crate::{ast::{AstNode, Function, Value}, env::Environment};
+
+// How many steps `:steps` and the ci-gui trace show before giving up on reaching a normal form
+pub const DEFAULT_STEP_LIMIT: usize = 100;
+
+// Which redex gets reduced first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    // leftmost outermost, arguments are substituted unevaluated
+    #[default]
+    Normal,
+    // leftmost innermost, arguments are reduced before they're substituted
+    Applicative,
+}
+
+impl std::str::FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(Order::Normal),
+            "applicative" => Ok(Order::Applicative),
+            other => Err(format!("unknown order {other:?}, expected normal or applicative")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    // `((fn 'x body) arg)` to `body` with `arg` in place of `x`
+    Beta,
+    // a name was replaced by the function it's defined as
+    Unfold(String),
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::Beta => write!(f, "beta"),
+            Rule::Unfold(name) => write!(f, "unfold {name}"),
+        }
+    }
+}
+
+pub struct ReductionStep {
+    pub rule: Rule,
+    // the whole term after this step
+    pub term: AstNode,
+}
+
+impl std::fmt::Display for ReductionStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "-> {}    [{}]", self.term, self.rule)
+    }
+}
+
+// Reduce `term` symbolically, one step at a time, until it's in normal form or `limit` steps
+// have been taken. Names defined in `env` as functions are unfolded when they're applied,
+// anything else (numbers, built-in functions, ...) is left as it is.
+pub fn reduce_steps(term: &AstNode, env: &Environment, order: Order, limit: usize) -> Vec<ReductionStep> {
+    let mut term = strip_spans(term);
+    let mut steps = Vec::new();
+
+    while steps.len() < limit {
+        let Some((rule, next)) = step(&term, env, order) else {
+            break;
+        };
+
+        steps.push(ReductionStep { rule, term: next.as_ref().clone() });
+        term = next;
+    }
+
+    steps
+}
+
+// The next step `term` takes, or None if it's in normal form
+pub fn step(term: &Rc<AstNode>, env: &Environment, order: Order) -> Option<(Rule, Rc<AstNode>)> {
+    match order {
+        Order::Normal => step_normal(term, env, &HashSet::new()),
+        Order::Applicative => step_applicative(term, env, &HashSet::new()),
+    }
+}
+
+// `term` without any source locations
+pub fn strip_spans(term: &AstNode) -> Rc<AstNode> {
+    match term {
+        AstNode::Spanned { span: _, node } => strip_spans(node),
+        AstNode::Par { car, cdr } => Rc::new(AstNode::Par { car: strip_spans(car), cdr: strip_spans(cdr) }),
+        AstNode::Lambda { varname, body } => Rc::new(AstNode::Lambda { varname: varname.clone(), body: strip_spans(body) }),
+        other => Rc::new(other.clone()),
+    }
+}
+
+pub fn free_vars(term: &AstNode) -> HashSet<String> {
+    fn collect(term: &AstNode, bound: &mut Vec<String>, free: &mut HashSet<String>) {
+        match term.unspanned() {
+            AstNode::Value(Value::Symbol(s)) if !bound.contains(s) => { free.insert(s.clone()); }
+            AstNode::Par { car, cdr } => {
+                collect(car, bound, free);
+                collect(cdr, bound, free);
+            }
+            AstNode::Lambda { varname, body } => {
+                bound.push(varname.clone());
+                collect(body, bound, free);
+                bound.pop();
+            }
+            _ => (),
+        }
+    }
+
+    let mut free = HashSet::new();
+    collect(term, &mut Vec::new(), &mut free);
+    free
+}
+
+// `term` with `value` in place of the free occurrences of `var`, renaming the
+// parameters that would otherwise capture one of `value`'s free variables
+pub fn substitute(term: &Rc<AstNode>, var: &str, value: &Rc<AstNode>) -> Rc<AstNode> {
+    substitute_free(term, var, value, &free_vars(value))
+}
+
+fn substitute_free(term: &Rc<AstNode>, var: &str, value: &Rc<AstNode>, value_free: &HashSet<String>) -> Rc<AstNode> {
+    match term.unspanned() {
+        AstNode::Value(Value::Symbol(s)) if s == var => value.clone(),
+        AstNode::Par { car, cdr } => Rc::new(AstNode::Par {
+            car: substitute_free(car, var, value, value_free),
+            cdr: substitute_free(cdr, var, value, value_free),
+        }),
+        AstNode::Lambda { varname, body: _ } if varname == var => term.clone(),
+        AstNode::Lambda { varname, body } if value_free.contains(varname) && free_vars(body).contains(var) => {
+            let mut taken = free_vars(body);
+            taken.extend(value_free.iter().cloned());
+            taken.insert(var.to_string());
+            let fresh = fresh_name(varname, &taken);
+
+            let body = substitute(body, varname, &Rc::new(AstNode::Value(Value::Symbol(fresh.clone()))));
+            Rc::new(AstNode::Lambda { varname: fresh, body: substitute_free(&body, var, value, value_free) })
+        }
+        AstNode::Lambda { varname, body } => Rc::new(AstNode::Lambda {
+            varname: varname.clone(),
+            body: substitute_free(body, var, value, value_free),
+        }),
+        _ => term.clone(),
+    }
+}
+
+// `x1`, `x2`, ... whichever isn't taken yet
+fn fresh_name(name: &str, taken: &HashSet<String>) -> String {
+    let base = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    (1..)
+        .map(|i| format!("{base}{i}"))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
+}
+
+// The definition of `name` as a lambda, if it's a function defined in ci-lisp.
+// It's left alone if a parameter in scope would capture one of the names it uses
+fn unfold(name: &str, env: &Environment, bound: &HashSet<String>) -> Option<Rc<AstNode>> {
+    if bound.contains(name) {
+        return None;
+    }
+
+    let AstNode::Function(Function::User { varname, body, doc: _, name: _, env: _ }) = &env.get(name)? else {
+        return None;
+    };
+    let lambda = Rc::new(AstNode::Lambda { varname: varname.clone(), body: strip_spans(body) });
+
+    match free_vars(&lambda).is_disjoint(bound) {
+        true => Some(lambda),
+        false => None,
+    }
+}
+
+// One step of the redex in function position, if there is one there
+fn step_head(car: &Rc<AstNode>, cdr: &Rc<AstNode>, env: &Environment, bound: &HashSet<String>) -> Option<(Rule, Rc<AstNode>)> {
+    match car.as_ref() {
+        AstNode::Lambda { varname, body } => Some((Rule::Beta, substitute(body, varname, cdr))),
+        AstNode::Value(Value::Symbol(s)) => unfold(s, env, bound)
+            .map(|f| (Rule::Unfold(s.clone()), Rc::new(AstNode::Par { car: f, cdr: cdr.clone() }))),
+        _ => None,
+    }
+}
+
+fn step_normal(term: &Rc<AstNode>, env: &Environment, bound: &HashSet<String>) -> Option<(Rule, Rc<AstNode>)> {
+    match term.as_ref() {
+        AstNode::Par { car, cdr } => step_head(car, cdr, env, bound)
+            .or_else(|| step_normal(car, env, bound)
+                .map(|(rule, car)| (rule, Rc::new(AstNode::Par { car, cdr: cdr.clone() }))))
+            .or_else(|| step_normal(cdr, env, bound)
+                .map(|(rule, cdr)| (rule, Rc::new(AstNode::Par { car: car.clone(), cdr })))),
+
+        AstNode::Lambda { varname, body } => {
+            let mut bound = bound.clone();
+            bound.insert(varname.clone());
+            step_normal(body, env, &bound)
+                .map(|(rule, body)| (rule, Rc::new(AstNode::Lambda { varname: varname.clone(), body })))
+        }
+
+        _ => None,
+    }
+}
+
+fn step_applicative(term: &Rc<AstNode>, env: &Environment, bound: &HashSet<String>) -> Option<(Rule, Rc<AstNode>)> {
+    match term.as_ref() {
+        AstNode::Par { car, cdr } => step_applicative(car, env, bound)
+                .map(|(rule, car)| (rule, Rc::new(AstNode::Par { car, cdr: cdr.clone() })))
+            .or_else(|| step_applicative(cdr, env, bound)
+                .map(|(rule, cdr)| (rule, Rc::new(AstNode::Par { car: car.clone(), cdr }))))
+            .or_else(|| step_head(car, cdr, env, bound)),
+
+        AstNode::Lambda { varname, body } => {
+            let mut bound = bound.clone();
+            bound.insert(varname.clone());
+            step_applicative(body, env, &bound)
+                .map(|(rule, body)| (rule, Rc::new(AstNode::Lambda { varname: varname.clone(), body })))
+        }
+
+        _ => None,
+    }
+}
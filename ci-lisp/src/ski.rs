@@ -0,0 +1,90 @@
+use std::rc::Rc;
+
+use crate::{ast::{AstNode, Value}, env::Environment, limits::ResourceKind, parsers::CIEvalError, reduce::{free_vars, step, strip_spans, Order}};
+
+// The combinators, by the names `lib/ext_math.ci` gives them, so compiled terms run with it loaded
+pub const S: &str = "fork";
+pub const K: &str = "const";
+pub const I: &str = "id";
+
+// How many steps `from_ski` takes looking for a normal form before giving up
+pub const MAX_SKI_STEPS: usize = 10_000;
+
+// `term` with its lambdas compiled away into S, K and I
+pub fn to_ski(term: &AstNode) -> Rc<AstNode> {
+    match term.unspanned() {
+        AstNode::Lambda { varname, body } => abstract_var(varname, &to_ski(body)),
+        AstNode::Par { car, cdr } => app(to_ski(car), to_ski(cdr)),
+        other => Rc::new(other.clone()),
+    }
+}
+
+// A combinator term that gives `term` when it's applied to `var`. `term` has no lambdas left in it
+fn abstract_var(var: &str, term: &Rc<AstNode>) -> Rc<AstNode> {
+    if !free_vars(term).contains(var) {
+        return app(sym(K), term.clone());
+    }
+
+    match term.as_ref() {
+        AstNode::Par { car, cdr } => app(app(sym(S), abstract_var(var, car)), abstract_var(var, cdr)),
+        // the only thing `var` can be free in besides an application is `var` itself
+        _ => sym(I),
+    }
+}
+
+// A combinator term back as lambdas, in normal form. Parameters come out named after
+// the ones in `lib/ext_math.ci`, renamed where they'd clash
+pub fn from_ski(term: &AstNode) -> Result<AstNode, CIEvalError> {
+    let mut term = expand_combinators(&strip_spans(term));
+
+    for _ in 0..MAX_SKI_STEPS {
+        match step(&term, &Environment::default(), Order::Normal) {
+            Some((_, next)) => term = next,
+            None => return Ok(term.as_ref().clone()),
+        }
+    }
+
+    Err(CIEvalError::ResourceExhausted { kind: ResourceKind::Steps, limit: MAX_SKI_STEPS })
+}
+
+fn expand_combinators(term: &Rc<AstNode>) -> Rc<AstNode> {
+    match term.as_ref() {
+        AstNode::Value(Value::Symbol(s)) if s == S => {
+            lam("f", lam("g", lam("a", app(app(sym("f"), sym("a")), app(sym("g"), sym("a"))))))
+        }
+        AstNode::Value(Value::Symbol(s)) if s == K => lam("x", lam("_", sym("x"))),
+        AstNode::Value(Value::Symbol(s)) if s == I => lam("x", sym("x")),
+        AstNode::Par { car, cdr } => app(expand_combinators(car), expand_combinators(cdr)),
+        AstNode::Lambda { varname, body } if ![S, K, I].contains(&varname.as_str()) => lam(varname, expand_combinators(body)),
+        _ => term.clone(),
+    }
+}
+
+fn sym(name: &str) -> Rc<AstNode> {
+    Rc::new(AstNode::Value(Value::Symbol(name.to_string())))
+}
+
+fn app(car: Rc<AstNode>, cdr: Rc<AstNode>) -> Rc<AstNode> {
+    Rc::new(AstNode::Par { car, cdr })
+}
+
+fn lam(varname: &str, body: Rc<AstNode>) -> Rc<AstNode> {
+    Rc::new(AstNode::Lambda { varname: varname.to_string(), body })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::eval_lib;
+
+    #[test]
+    fn to_ski_compiles_lambdas_away() {
+        assert_eq!(eval_lib("(to_ski '(fn 'x (fn 'x x)))"), "'(const id)");
+        assert_eq!(eval_lib("(to_ski '(fn 'x (fn 'y (y x))))"), "'((fork (const (fork id))) ((fork (const const)) id))");
+    }
+
+    #[test]
+    fn from_ski_round_trips() {
+        assert_eq!(eval_lib("((alpha_eq (from_ski (to_ski compose))) compose)"), "t");
+        assert_eq!(eval_lib("((alpha_eq (from_ski '((fork const) const))) '(fn 'x x))"), "t");
+    }
+}
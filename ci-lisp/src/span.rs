@@ -0,0 +1,151 @@
+use std::rc::Rc;
+
+// A named piece of source text. Every span points back into one of these,
+// so errors can render themselves without the caller keeping the input around.
+#[derive(PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: &str, text: &str) -> Rc<Self> {
+        Rc::new(Self { name: name.to_string(), text: text.to_string() })
+    }
+}
+
+impl std::fmt::Debug for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Span {
+    pub source: Rc<Source>,
+    // byte offsets into `source.text`, end is exclusive
+    pub start: usize,
+    pub end: usize,
+    // 1-based line and character column of `start`
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Debug for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name, self.line, self.col)
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Span {
+    /// The smallest span covering both `self` and `other`
+    pub fn to(&self, other: &Span) -> Span {
+        if other.start < self.start {
+            return other.to(self);
+        }
+
+        Span {
+            source: self.source.clone(),
+            start: self.start,
+            end: self.end.max(other.end),
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.source.text[self.start..self.end.min(self.source.text.len())]
+    }
+
+    /// Render a rustc-style snippet with carets under the spanned text:
+    /// ```text
+    /// error: Unknown symbol: foo
+    ///  --> repl:1:6
+    ///   |
+    /// 1 | (add foo)
+    ///   |      ^^^
+    /// ```
+    pub fn render(&self, level: &str, message: &str) -> String {
+        let text = &self.source.text;
+        let line_start = text[..self.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = text[line_start..].find('\n').map(|i| line_start + i).unwrap_or(text.len());
+        let line_text = &text[line_start..line_end];
+
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        // carets stop at the end of the first line for multi-line spans
+        let caret_start = text[line_start..self.start].chars().count();
+        let caret_len = text[self.start..self.end.clamp(self.start, line_end)].chars().count().max(1);
+
+        format!(
+            "{level}: {message}\n{gutter}--> {self}\n{gutter} |\n{} | {line_text}\n{gutter} | {}{}",
+            self.line,
+            " ".repeat(caret_start),
+            "^".repeat(caret_len),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.node)
+    }
+}
+
+// Tracks the position of the next character while a lexer walks its input
+pub struct SpanTracker {
+    source: Rc<Source>,
+    offset: usize,
+    line: usize,
+    col: usize,
+}
+
+impl SpanTracker {
+    pub fn new(source: Rc<Source>) -> Self {
+        Self { source, offset: 0, line: 1, col: 1 }
+    }
+
+    /// A zero-width span at the current position
+    pub fn here(&self) -> Span {
+        Span {
+            source: self.source.clone(),
+            start: self.offset,
+            end: self.offset,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    /// A span from `start` up to the current position
+    pub fn since(&self, start: &Span) -> Span {
+        Span { end: self.offset, ..start.clone() }
+    }
+
+    pub fn advance(&mut self, ch: char) {
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// Shared by the tests in this crate
+use crate::{ast::AstNode, env::{float::float_environment, math::math_environment, prelude::prelude_environment, string::string_environment, Environment}, parser_types::{CIParserError, Parser, SeqParsers}, parsers::{CIIntermediateTokenizer, CILexer, CINewReplParser, CIReplEvaluator}};
+
+// where `range`, `len`, `foldr` and the other list functions are defined
+pub const EXT_MATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../lib/ext_math.ci");
+
+// what `ci-term --math --float --string` starts with
+pub fn full_environment() -> Environment {
+    string_environment(float_environment(math_environment(prelude_environment(Environment::default()))))
+}
+
+// Evaluate a line in `env` the way the repl does, after including the `preload` files
+pub fn eval_in(env: Environment, preload: &[&str], source: &str) -> Result<AstNode, CIParserError> {
+    let parser = SeqParsers::new(
+        SeqParsers::new(CILexer::new("test"), CIIntermediateTokenizer::default()),
+        SeqParsers::new(CINewReplParser::default(), CIReplEvaluator::new(preload.iter().map(|p| p.to_string()).collect(), env))
+    );
+
+    parser.parse(source.to_string())
+}
+
+// the value of a line as the repl would print it, with lib/ext_math.ci loaded
+pub fn eval_lib(source: &str) -> String {
+    eval_in(full_environment(), &[EXT_MATH], source).unwrap().to_string()
+}
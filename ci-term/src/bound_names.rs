@@ -0,0 +1,39 @@
+use std::{collections::BTreeMap, sync::{Arc, Mutex}};
+
+use ci_lisp::{ast::{AstNode, Function}, env::Environment};
+
+// The names bound in the repl's environment, with the first line of their docs.
+// Reedline's helpers have to be Send, so they get this copy instead of the environment itself
+#[derive(Clone, Default)]
+pub struct BoundNames {
+    names: Arc<Mutex<BTreeMap<String, Option<String>>>>,
+}
+
+impl BoundNames {
+    pub fn update(&self, env: &Environment) {
+        let names = env.names()
+            .map(|name| {
+                let doc = match &env.get(name) {
+                    Some(AstNode::Function(Function::User { doc: Some(doc), .. })) => doc.lines().next().map(str::to_string),
+                    _ => None,
+                };
+                (name.clone(), doc)
+            })
+            .collect();
+
+        *self.names.lock().unwrap() = names;
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.lock().unwrap().contains_key(name)
+    }
+
+    // the names starting with `prefix`, in order, with their docs
+    pub fn starting_with(&self, prefix: &str) -> Vec<(String, Option<String>)> {
+        self.names.lock().unwrap()
+            .range(prefix.to_string()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .map(|(name, doc)| (name.clone(), doc.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+use std::{fs, path::Path};
+
+use reedline::{Completer, Span, Suggestion};
+
+use crate::BoundNames;
+
+// Completes the names bound in the repl, as symbols or `'idents`, and file paths in `(include "...")`
+pub struct CICompleter {
+    names: BoundNames,
+}
+
+impl CICompleter {
+    pub fn new(names: BoundNames) -> Self {
+        Self { names }
+    }
+
+    fn complete_name(&self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || "()[]{}\"`,#".contains(c))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let (quote, prefix) = match word.strip_prefix('\'') {
+            Some(prefix) => ("'", prefix),
+            None => ("", word),
+        };
+
+        self.names.starting_with(prefix)
+            .into_iter()
+            // the built-in versions behind the library functions, only offered when asked for
+            .filter(|(name, _)| !name.starts_with("builtin__") || prefix.starts_with("builtin__"))
+            .map(|(name, doc)| Suggestion {
+                value: format!("{quote}{name}"),
+                description: doc,
+                span: Span::new(start, pos),
+                append_whitespace: true,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn complete_path(partial: &str, span: Span) -> Vec<Suggestion> {
+        let (dir, file) = match partial.rfind('/') {
+            Some(i) => (&partial[..=i], &partial[i + 1..]),
+            None => ("", partial),
+        };
+        let Ok(entries) = fs::read_dir(if dir.is_empty() { Path::new(".") } else { Path::new(dir) }) else {
+            return Vec::new();
+        };
+
+        let mut paths: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                if !name.starts_with(file) || (name.starts_with('.') && !file.starts_with('.')) {
+                    return None;
+                }
+                let slash = if entry.path().is_dir() { "/" } else { "" };
+                Some(format!("{dir}{name}{slash}"))
+            })
+            .collect();
+        paths.sort();
+
+        paths.into_iter()
+            .map(|value| Suggestion { value, span, ..Default::default() })
+            .collect()
+    }
+
+    // where the string the cursor is in starts, if it's the file name of an include
+    fn include_string(before: &str) -> Option<usize> {
+        let mut open = None;
+        let mut escaped = false;
+        for (i, c) in before.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if open.is_some() => escaped = true,
+                '"' => open = if open.is_some() { None } else { Some(i) },
+                _ => (),
+            }
+        }
+
+        let quote = open?;
+        before[..quote].trim_end().strip_suffix("include")?.trim_end().ends_with('(').then_some(quote + 1)
+    }
+}
+
+impl Completer for CICompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        match Self::include_string(&line[..pos]) {
+            Some(start) => Self::complete_path(&line[start..pos], Span::new(start, pos)),
+            None => self.complete_name(line, pos),
+        }
+    }
+}
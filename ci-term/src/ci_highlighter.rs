@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+
+use ci_lisp::{ast::{Token, Value}, palette::{token_rgb, UNBOUND_RGB}, parser_types::Parser, parsers::CIStreamingLexer, span::Spanned};
+use nu_ansi_term::{Color, Style};
+use reedline::{Highlighter, StyledText};
+
+use crate::BoundNames;
+
+// Symbols the parser handles itself, which are never bound
+const KEYWORDS: [&str; 5] = ["fn", "cond", "quote", "quasiquote", "unquote"];
+
+// Colours the input the way ci-gui does, marking the bracket that matches the one at the cursor,
+// symbols that aren't bound to anything, and strings that haven't been closed
+pub struct CIHighlighter {
+    names: BoundNames,
+}
+
+impl CIHighlighter {
+    pub fn new(names: BoundNames) -> Self {
+        Self { names }
+    }
+
+    fn rgb((r, g, b): (u8, u8, u8)) -> Style {
+        Style::new().fg(Color::Rgb(r, g, b))
+    }
+
+    // the positions of the bracket at (or just before) the cursor and the one matching it
+    fn matching_brackets(tokens: &[Spanned<Token>], cursor: usize) -> Option<(usize, usize)> {
+        let mut open = Vec::new();
+        let mut pairs = Vec::new();
+        for token in tokens {
+            match token.node {
+                Token::LParen | Token::LCurly | Token::LBracket => open.push(token.span.start),
+                Token::RParen | Token::RCurly | Token::RBracket => {
+                    if let Some(start) = open.pop() {
+                        pairs.push((start, token.span.start));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let at = |pos: usize| pairs.iter().find(|(open, close)| *open == pos || *close == pos).copied();
+        at(cursor).or_else(|| cursor.checked_sub(1).and_then(at))
+    }
+
+    fn style(&self, token: &Token, bindable: &HashSet<&str>) -> Style {
+        match token {
+            Token::Value(Value::Symbol(s))
+                if !self.names.contains(s) && !bindable.contains(s.as_str()) && !KEYWORDS.contains(&s.as_str()) && !s.starts_with(':') => {
+                Self::rgb(UNBOUND_RGB)
+            }
+            other => Self::rgb(token_rgb(other)),
+        }
+    }
+}
+
+impl Highlighter for CIHighlighter {
+    fn highlight(&self, line: &str, cursor: usize) -> StyledText {
+        let mut styled = StyledText::new();
+
+        // the lexer gives up on invalid escapes, the parser will point those out
+        let Ok(tokens) = CIStreamingLexer::new("repl").parse(line.to_string()) else {
+            styled.push((Style::new(), line.to_string()));
+            return styled;
+        };
+
+        // names given as 'idents might be parameters or definitions, so they don't count as unbound
+        let bindable: HashSet<&str> = tokens.iter()
+            .filter_map(|token| match &token.node {
+                Token::Value(Value::Ident(name)) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        let brackets = Self::matching_brackets(&tokens, cursor);
+
+        let mut pos = 0;
+        for token in tokens.iter().filter(|token| token.node != Token::EOF) {
+            let start = token.span.start.min(line.len());
+            let end = token.span.end.min(line.len());
+
+            // whitespace and comments
+            if start > pos {
+                styled.push((Style::new().fg(Color::DarkGray), line[pos..start].to_string()));
+            }
+
+            let style = match &token.node {
+                _ if brackets.is_some_and(|(open, close)| start == open || start == close) => {
+                    self.style(&token.node, &bindable).bold().reverse()
+                }
+                // the lexer closes a string that's still open, past the end of the line
+                Token::Value(Value::String(_)) if token.span.end > line.len() => Self::rgb(UNBOUND_RGB).underline(),
+                other => self.style(other, &bindable),
+            };
+            styled.push((style, line[start..end].to_string()));
+            pos = end;
+        }
+
+        if pos < line.len() {
+            styled.push((Style::new().fg(Color::DarkGray), line[pos..].to_string()));
+        }
+
+        styled
+    }
+}
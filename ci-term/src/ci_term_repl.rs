@@ -1,69 +1,324 @@
-use std::cell::RefCell;
-use reedline::{DefaultPrompt, DefaultPromptSegment, Reedline, Signal};
+use std::{cell::{Cell, RefCell}, sync::{atomic::AtomicUsize, Arc}, time::Instant};
+use reedline::{default_emacs_keybindings, ColumnarMenu, DefaultPrompt, DefaultPromptSegment, Emacs, KeyCode, KeyModifiers, MenuBuilder, Reedline, ReedlineEvent, ReedlineMenu, Signal};
 
-use ci_lisp::{parser_types::Parser};
-use crate::{CIReplError, ReadSignal, Repl};
+use ci_lisp::{ast::{AstNode, Function}, env::Environment, limits::Limits, parser_types::{HasEnv, Parser, SeqParsers}, parsers::{CIIntermediateTokenizer, CILexer, CINewReplParser}, reduce::{reduce_steps, strip_spans, Order, DEFAULT_STEP_LIMIT}};
+use crate::{run_program, AutoIndent, BoundNames, CICompleter, CIHighlighter, CIReplError, CIValidator, ReadSignal, Repl, ReplOutput};
+
+// Reads the terms given to `:commands`, without evaluating them
+type TermParser = SeqParsers<SeqParsers<CILexer, CIIntermediateTokenizer>, CINewReplParser>;
+
+// What `:help` lists
+const COMMANDS: [(&str, &str); 9] = [
+    (":load file", "evaluate a file, keeping what it defines"),
+    (":reload", "start over, then load the files loaded so far again"),
+    (":env [prefix]", "list the bound names starting with prefix"),
+    (":reset", "forget everything defined since the repl started"),
+    (":time expr", "evaluate expr and show how long it took"),
+    (":doc name", "show the doc and definition of name"),
+    (":steps [normal|applicative] term", "show term reducing one beta step at a time"),
+    (":quit", "leave the repl"),
+    (":help", "show this list"),
+];
 
 pub struct CITermRepl<P> {
     line_editor: RefCell<Reedline>,
     prompt: DefaultPrompt,
 
     parser: P,
+    term_parser: TermParser,
+    // what the line editor completes, kept up to date with the parser's environment
+    names: BoundNames,
+
+    // what `:reset` goes back to: the environment the parser started with, plus the preloaded files
+    initial_env: Environment,
+    preload: Vec<String>,
+    // the files given to `:load`, for `:reload`
+    loaded: RefCell<Vec<String>>,
+    quit: Cell<bool>,
 }
 
-impl<P> CITermRepl<P> {
+impl<P: HasEnv> CITermRepl<P> {
     pub fn new(parser: P) -> Self {
+        let names = BoundNames::default();
         Self {
-            line_editor: RefCell::new(Reedline::create()),
+            line_editor: RefCell::new(Self::line_editor(&names)),
             prompt: DefaultPrompt::new(
                 DefaultPromptSegment::Empty,
                 DefaultPromptSegment::Empty
             ),
+            initial_env: parser.env(),
             parser,
+            term_parser: Self::term_parser(),
+            names,
+            preload: Vec::new(),
+            loaded: RefCell::new(Vec::new()),
+            quit: Cell::new(false),
         }
     }
+
+    // Load these files now, and again after every `:reset`
+    pub fn with_preload(mut self, preload: Vec<String>) -> Result<Self, CIReplError> {
+        self.preload = preload;
+        self.reset()?;
+        Ok(self)
+    }
+
+    // Back to the parser's initial environment and the preloaded files, forgetting what `:load` loaded.
+    // The preloaded files aren't held to the initial environment's limits, only what comes after them is
+    pub fn reset(&self) -> Result<(), CIReplError> {
+        let limits = self.initial_env.budget().limits();
+        self.parser.set_env(self.initial_env.with_limits(Limits::unlimited()));
+        self.loaded.borrow_mut().clear();
+
+        let preloaded = self.preload.iter().try_for_each(|file| self.load(file));
+        self.parser.set_env(self.parser.env().with_limits(limits));
+        preloaded
+    }
+
+    // Evaluate a file in the current environment, keeping what it defines
+    fn load(&self, file: &str) -> Result<(), CIReplError> {
+        let source = std::fs::read_to_string(file)
+            .map_err(|e| CIReplError::CommandError(format!("can't read {file}: {e}")))?;
+
+        let (_, env) = run_program(source, file, self.parser.env())?;
+        self.parser.set_env(env);
+        Ok(())
+    }
 }
 
-impl<P: Default> Default for CITermRepl<P> {
+impl<P> CITermRepl<P> {
+    // Enter only submits once the brackets are balanced, otherwise it starts an indented line.
+    // Tab completes from `names`, which also tells the highlighting what's unbound
+    fn line_editor(names: &BoundNames) -> Reedline {
+        let depth = Arc::new(AtomicUsize::new(0));
+
+        let mut keybindings = default_emacs_keybindings();
+        keybindings.add_binding(
+            KeyModifiers::NONE,
+            KeyCode::Tab,
+            ReedlineEvent::UntilFound(vec![
+                ReedlineEvent::Menu("completion_menu".to_string()),
+                ReedlineEvent::MenuNext,
+            ]),
+        );
+
+        Reedline::create()
+            .with_validator(Box::new(CIValidator::new(depth.clone())))
+            .with_edit_mode(Box::new(AutoIndent::new(Emacs::new(keybindings), depth)))
+            .with_completer(Box::new(CICompleter::new(names.clone())))
+            .with_highlighter(Box::new(CIHighlighter::new(names.clone())))
+            .with_menu(ReedlineMenu::EngineCompleter(Box::new(ColumnarMenu::default().with_name("completion_menu"))))
+            .use_bracketed_paste(true)
+    }
+
+    fn term_parser() -> TermParser {
+        SeqParsers::new(
+            SeqParsers::new(CILexer::new("repl"), CIIntermediateTokenizer::default()),
+            CINewReplParser::default()
+        )
+    }
+}
+
+impl<P: Default + HasEnv> Default for CITermRepl<P> {
     fn default() -> Self {
-        Self {
-            line_editor: RefCell::new(Reedline::create()),
-            prompt: DefaultPrompt::new(
-                DefaultPromptSegment::Empty,
-                DefaultPromptSegment::Empty
-            ),
-            parser: P::default()
+        Self::new(P::default())
+    }
+}
+
+impl<P, O> CITermRepl<P>
+where
+    O: std::fmt::Debug,
+    P: Parser<Input = String, Output = O> + HasEnv
+{
+    // `:name args`, handled here rather than by the language
+    fn command(&self, command: &str) -> Result<ReplOutput<O>, CIReplError> {
+        let (name, args) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let args = args.trim();
+
+        let text = match name {
+            "load" => self.load_command(args)?,
+            "reload" => self.reload()?,
+            "env" => self.env_command(args),
+            "reset" => {
+                self.reset()?;
+                "back to the start".to_string()
+            }
+            "time" => self.time(args)?,
+            "doc" => self.doc(args)?,
+            "steps" => self.steps(args)?,
+            "quit" => {
+                self.quit.set(true);
+                return Ok(ReplOutput::Nothing);
+            }
+            "help" => Self::help(),
+            other => return Err(CIReplError::CommandError(format!("unknown command :{other}, see :help"))),
+        };
+
+        Ok(ReplOutput::Text(text))
+    }
+
+    fn usage(usage: &str) -> CIReplError {
+        CIReplError::CommandError(format!("usage: {usage}"))
+    }
+
+    // `:load file`
+    fn load_command(&self, file: &str) -> Result<String, CIReplError> {
+        if file.is_empty() {
+            return Err(Self::usage(":load file"));
+        }
+
+        self.load(file)?;
+        let mut loaded = self.loaded.borrow_mut();
+        if !loaded.iter().any(|f| f == file) {
+            loaded.push(file.to_string());
+        }
+
+        Ok(format!("loaded {file}"))
+    }
+
+    // `:reload`, the files are loaded in the order they were first loaded, stopping at the first one that fails
+    fn reload(&self) -> Result<String, CIReplError> {
+        let files = self.loaded.take();
+        self.reset()?;
+        *self.loaded.borrow_mut() = files.clone();
+
+        for file in &files {
+            self.load(file)?;
+        }
+
+        Ok(match files.len() {
+            1 => "reloaded 1 file".to_string(),
+            n => format!("reloaded {n} files"),
+        })
+    }
+
+    // `:env [prefix]`, with the first line of each one's doc
+    fn env_command(&self, prefix: &str) -> String {
+        self.names.update(&self.parser.env());
+        let names: Vec<_> = self.names.starting_with(prefix)
+            .into_iter()
+            .filter(|(name, _)| !name.starts_with("builtin__") || prefix.starts_with("builtin__"))
+            .collect();
+
+        if names.is_empty() {
+            return format!("nothing bound starting with {prefix:?}");
+        }
+
+        let width = names.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        names.iter()
+            .map(|(name, doc)| match doc {
+                Some(doc) => format!("{name:width$}  {doc}"),
+                None => name.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // `:time expr`
+    fn time(&self, source: &str) -> Result<String, CIReplError> {
+        if source.is_empty() {
+            return Err(Self::usage(":time expr"));
+        }
+
+        let start = Instant::now();
+        let value = self.parser.parse(source.to_string())?;
+        Ok(format!("{value:?}\ntook {:?}", start.elapsed()))
+    }
+
+    // `:doc name`
+    fn doc(&self, name: &str) -> Result<String, CIReplError> {
+        let name = name.strip_prefix('\'').unwrap_or(name);
+        if name.is_empty() {
+            return Err(Self::usage(":doc name"));
+        }
+
+        let value = self.parser.env().get(name)
+            .ok_or_else(|| CIReplError::CommandError(format!("{name} isn't bound")))?;
+
+        Ok(match &value {
+            AstNode::Function(Function::User { varname, body, doc, name: _, env: _ }) => {
+                let doc = doc.clone().unwrap_or_else(|| format!("{name} has no doc"));
+                format!("{doc}\n(fn '{varname} {body})")
+            }
+            AstNode::Function(Function::Native(_) | Function::NativeMutEnv(_)) => format!("{name} is a native function"),
+            AstNode::Function(Function::Special(_)) => format!("{name} is a special form"),
+            AstNode::Function(Function::Macro(_)) => format!("{name} is a macro"),
+            other => format!("{other}"),
+        })
+    }
+
+    // `:help`
+    fn help() -> String {
+        let width = COMMANDS.iter().map(|(usage, _)| usage.len()).max().unwrap_or(0);
+        COMMANDS.iter()
+            .map(|(usage, description)| format!("{usage:width$}  {description}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // `:steps [normal|applicative] term`
+    fn steps(&self, args: &str) -> Result<String, CIReplError> {
+        let (order, source) = match args.split_once(char::is_whitespace) {
+            Some((order, source)) if order.parse::<Order>().is_ok() => (order.parse().unwrap(), source),
+            _ => (Order::default(), args),
+        };
+        if source.trim().is_empty() {
+            return Err(Self::usage(":steps [normal|applicative] term"));
         }
+
+        let term = strip_spans(&self.term_parser.parse(source.to_string())?);
+        let steps = reduce_steps(&term, &self.parser.env(), order, DEFAULT_STEP_LIMIT);
+
+        let mut lines = vec![format!("   {term}")];
+        lines.extend(steps.iter().map(|step| step.to_string()));
+        lines.push(match steps.len() {
+            DEFAULT_STEP_LIMIT => format!("stopped after {DEFAULT_STEP_LIMIT} steps"),
+            1 => "normal form after 1 step".to_string(),
+            n => format!("normal form after {n} steps"),
+        });
+
+        Ok(lines.join("\n"))
     }
 }
 
 impl<P, O> Repl for CITermRepl<P>
 where
     O: std::fmt::Debug,
-    P: Parser<Input = String, Output = O>
+    P: Parser<Input = String, Output = O> + HasEnv
 {
     type Input = String;
-    type Output = O;
+    type Output = ReplOutput<O>;
 
     fn read(&self) -> Result<ReadSignal<Self::Input>, CIReplError> {
+        if self.quit.get() {
+            return Ok(ReadSignal::Quit);
+        }
+
+        self.names.update(&self.parser.env());
         let mut line_editor = self.line_editor.borrow_mut();
 
         let sig = line_editor.read_line(&self.prompt)?;
         match sig {
-            Signal::Success(a) if a == String::from("") => Ok(ReadSignal::Nothing),
+            Signal::Success(a) if a.is_empty() => Ok(ReadSignal::Nothing),
             Signal::Success(buffer) => Ok(ReadSignal::Input(buffer)),
             Signal::CtrlD | Signal::CtrlC => Ok(ReadSignal::Quit),
         }
     }
 
     fn evaluate(&self, input: String) -> Result<Self::Output, CIReplError> {
-        Ok(self.parser.parse(input)?)
+        match input.trim_start().strip_prefix(':') {
+            Some(command) => self.command(command),
+            None => Ok(ReplOutput::Value(self.parser.parse(input)?)),
+        }
     }
 
     fn print(&self, output: Self::Output) -> Result<(), CIReplError> {
-        println!("{:?}", output);
+        match output {
+            ReplOutput::Value(value) => println!("{:?}", value),
+            ReplOutput::Text(text) => println!("{}", text),
+            ReplOutput::Nothing => (),
+        }
 
         Ok(())
     }
 }
-
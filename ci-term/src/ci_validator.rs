@@ -0,0 +1,79 @@
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+use ci_lisp::{parser_types::{CIParserError, Parser, SeqParsers}, parsers::{CIIntermediateTokenizer, CILexer, CILexerError}};
+use reedline::{EditCommand, EditMode, PromptEditMode, ReedlineEvent, ReedlineRawEvent, ValidationResult, Validator};
+
+const INDENT: &str = "  ";
+
+// Keeps the prompt open while a bracket, string or block comment is still open,
+// so definitions can span several lines
+pub struct CIValidator {
+    tokenizer: SeqParsers<CILexer, CIIntermediateTokenizer>,
+    // how many brackets are open, for the next line's indentation
+    depth: Arc<AtomicUsize>,
+}
+
+impl CIValidator {
+    pub fn new(depth: Arc<AtomicUsize>) -> Self {
+        Self {
+            tokenizer: SeqParsers::new(CILexer::new("repl"), CIIntermediateTokenizer::default()),
+            depth,
+        }
+    }
+}
+
+impl Validator for CIValidator {
+    fn validate(&self, line: &str) -> ValidationResult {
+        let (result, depth) = match self.tokenizer.parse(line.to_string()) {
+            Err(CIParserError::MissingCloseParen(level, _)) => (ValidationResult::Incomplete, level.max(0) as usize),
+            Err(CIParserError::LexerError(CILexerError::UnmatchedQuotes(_) | CILexerError::UnclosedComment(_))) => {
+                (ValidationResult::Incomplete, 0)
+            }
+            // anything else is for the parser to report
+            _ => (ValidationResult::Complete, 0),
+        };
+
+        self.depth.store(depth, Ordering::Relaxed);
+        result
+    }
+}
+
+// Indents a continuation line to the depth `CIValidator` left it at, as soon as something is typed on it
+pub struct AutoIndent<E: EditMode> {
+    edit_mode: E,
+    depth: Arc<AtomicUsize>,
+}
+
+impl<E: EditMode> AutoIndent<E> {
+    pub fn new(edit_mode: E, depth: Arc<AtomicUsize>) -> Self {
+        Self { edit_mode, depth }
+    }
+}
+
+impl<E: EditMode> EditMode for AutoIndent<E> {
+    fn parse_event(&mut self, event: ReedlineRawEvent) -> ReedlineEvent {
+        let event = self.edit_mode.parse_event(event);
+        // other events, like resizing the terminal or moving the cursor, leave the indent for the first thing typed
+        let depth = self.depth.load(Ordering::Relaxed);
+
+        match event {
+            ReedlineEvent::Edit(mut commands) if depth > 0 => {
+                // a closing bracket lines up with the line its opener is on
+                let depth = match commands.first() {
+                    Some(EditCommand::InsertChar(')' | ']' | '}')) => depth - 1,
+                    Some(EditCommand::InsertChar(_) | EditCommand::InsertString(_)) => depth,
+                    _ => return ReedlineEvent::Edit(commands),
+                };
+
+                self.depth.store(0, Ordering::Relaxed);
+                commands.insert(0, EditCommand::InsertString(INDENT.repeat(depth)));
+                ReedlineEvent::Edit(commands)
+            }
+            other => other,
+        }
+    }
+
+    fn edit_mode(&self) -> PromptEditMode {
+        self.edit_mode.edit_mode()
+    }
+}
@@ -1,6 +1,21 @@
 mod ci_term_repl;
 pub use ci_term_repl::CITermRepl;
 
+mod bound_names;
+pub use bound_names::BoundNames;
+
+mod ci_completer;
+pub use ci_completer::CICompleter;
+
+mod ci_highlighter;
+pub use ci_highlighter::CIHighlighter;
+
+mod ci_validator;
+pub use ci_validator::{AutoIndent, CIValidator};
+
+mod program;
+pub use program::run_program;
+
 use ci_lisp::parser_types::CIParserError;
 
 #[derive(Debug, thiserror::Error)]
@@ -8,8 +23,18 @@ pub enum CIReplError {
     #[error("IOError: {0}")]
     IOError(#[from] std::io::Error),
 
-    #[error("ParserError: {0}")]
-    ParserError(#[from] CIParserError)
+    #[error("{}", .0.render())]
+    ParserError(#[from] CIParserError),
+
+    #[error("error: {0}")]
+    CommandError(String)
+}
+
+// What an input produced: the parser's output, the text a `:command` wants shown, or nothing to show
+pub enum ReplOutput<O> {
+    Value(O),
+    Text(String),
+    Nothing
 }
 
 pub enum ReadSignal<InputType> {
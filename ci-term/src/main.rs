@@ -1,5 +1,7 @@
-use ci_lisp::{env::{math::math_environment, prelude::prelude_environment, Environment}, parser_types::SeqParsers, parsers::{CIIntermediateTokenizer, CILexer, CINewReplParser, CIReplEvaluator}};
-use ci_term::{CITermRepl, Repl};
+use std::{io::{IsTerminal, Read}, process::ExitCode};
+
+use ci_lisp::{ast::{AstNode, Value}, env::{float::float_environment, math::math_environment, prelude::{list_node, prelude_environment}, string::string_environment, Environment}, limits::{Limits, DEFAULT_MAX_DEPTH, DEFAULT_MAX_STEPS}, parser_types::SeqParsers, parsers::{CIIntermediateTokenizer, CILexer, CINewReplParser, CIReplEvaluator, Strategy}};
+use ci_term::{run_program, CITermRepl, Repl};
 use clap::Parser;
 
 #[derive(clap::Parser, Debug)]
@@ -15,10 +17,84 @@ struct Args {
 
     /// Enable built-in math functions. eg. add, sub, inc, dec, etc
     #[arg(long)]
-    math: bool
+    math: bool,
+
+    /// Enable built-in float functions. eg. sqrt, floor, pow, sin, etc
+    #[arg(long)]
+    float: bool,
+
+    /// Enable built-in string functions. eg. str_len, substr, split, upper, etc
+    #[arg(long)]
+    string: bool,
+
+    /// Give up on an input after this many reduction steps, so a runaway loop can't hang the repl
+    #[arg(long, default_value_t = DEFAULT_MAX_STEPS)]
+    max_steps: usize,
+
+    /// Give up when this many frames are pending at once, eg. in a runaway recursion
+    #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// Give up after this many allocations: closures created, arguments bound, thunks and native function results
+    #[arg(long)]
+    max_allocs: Option<usize>,
+
+    /// When function arguments are evaluated: value (before the call), name (every time they're used) or need (once, when first used)
+    #[arg(long, default_value = "value")]
+    strategy: Strategy,
+
+    /// Evaluate this expression and print its value, instead of starting the repl
+    #[arg(short = 'e')]
+    expr: Option<String>,
+
+    /// Script to run instead of starting the repl, followed by the arguments it gets as `args`
+    #[arg(trailing_var_arg = true)]
+    script: Vec<String>
 }
 
-fn main() {
+// Run a script, `-e` expression or piped program to completion, printing the value it ends with.
+// Scripts often end in a `def` or `include`, so their `nil` is only printed when `print_nil` is set.
+// Only the program itself is held to `limits`, not the preloaded libraries
+fn run_script(source: String, source_name: &str, preload: &[String], args: &[String], env: Environment, limits: Limits, print_nil: bool) -> ExitCode {
+    let mut env = env
+        .insert("args", list_node(args.iter().map(|arg| AstNode::Value(Value::String(arg.clone())))))
+        .with_limits(Limits::unlimited());
+
+    for file in preload {
+        let source = match std::fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("error: can't read {file}: {e}");
+                return ExitCode::from(2);
+            }
+        };
+
+        env = match run_program(source, file, env) {
+            Ok((_, env)) => env,
+            Err(e) => {
+                eprintln!("{}", e.render());
+                return ExitCode::FAILURE;
+            }
+        };
+    }
+
+    match run_program(source, source_name, env.with_limits(limits)) {
+        Ok((nodes, _)) => {
+            match nodes.last() {
+                Some(AstNode::Value(Value::Nil)) if !print_nil => (),
+                Some(last) => println!("{last}"),
+                None => (),
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e.render());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
 
     let mut env = Environment::default();
@@ -28,17 +104,59 @@ fn main() {
         env = math_environment(env);
     }
 
+    if args.float {
+        env = float_environment(env);
+    }
+
+    if args.string {
+        env = string_environment(env);
+    }
+
+    env = env.with_strategy(args.strategy);
+    let limits = Limits { steps: Some(args.max_steps), depth: Some(args.max_depth), allocs: args.max_allocs };
+
+    // anything to run besides the repl: `-e`, a script, or a program piped in
+    if let Some(expr) = args.expr {
+        return run_script(expr, "-e", &args.preload, &args.script, env, limits, true);
+    }
+    if let Some((script, script_args)) = args.script.split_first() {
+        return match std::fs::read_to_string(script) {
+            Ok(source) => run_script(source, script, &args.preload, script_args, env, limits, false),
+            Err(e) => {
+                eprintln!("error: can't read {script}: {e}");
+                ExitCode::from(2)
+            }
+        };
+    }
+    if !std::io::stdin().is_terminal() {
+        let mut source = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("error: can't read stdin: {e}");
+            return ExitCode::from(2);
+        }
+        return run_script(source, "stdin", &args.preload, &[], env, limits, false);
+    }
+
     let p = SeqParsers::new(
         SeqParsers::new(
-            CILexer::default(),
+            CILexer::new("repl"),
             CIIntermediateTokenizer::default()
         ),
         SeqParsers::new(
             CINewReplParser::new(args.infix_repl),
-            CIReplEvaluator::new(args.preload, env) 
+            CIReplEvaluator::new(Vec::new(), env.with_limits(limits))
         )
     );
 
-    let repl = CITermRepl::new(p);
-    repl.r#loop()
+    // the preloads are loaded by the repl, so `:reset` can bring them back
+    let repl = match CITermRepl::new(p).with_preload(args.preload) {
+        Ok(repl) => repl,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    repl.r#loop();
+
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,18 @@
+use ci_lisp::{ast::AstNode, env::Environment, parser_types::{CIParserError, HasEnv, Parser, SeqParsers}, parsers::{CIFileEvaluator, CIFullFileEvaluator, CIFullFileParser, CIIntermediateTokenizer, CILexer, CIMacroExpander, CINewFileParser}};
+
+// Evaluate a whole program, leaving what it defined in the returned environment
+pub fn run_program(source: String, source_name: &str, env: Environment) -> Result<(Vec<AstNode>, Environment), CIParserError> {
+    let evaluator: CIFullFileEvaluator = SeqParsers::new(
+        SeqParsers::new(
+            CIFullFileParser::new(
+                SeqParsers::new(CILexer::new(source_name), CIIntermediateTokenizer::default()),
+                CINewFileParser::default()
+            ),
+            CIMacroExpander::new(env.clone())
+        ),
+        CIFileEvaluator::new(env)
+    );
+
+    let nodes = evaluator.parse(source)?;
+    Ok((nodes, evaluator.env()))
+}